@@ -0,0 +1,121 @@
+use crate::Album;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// On-disk record of the checksums that have already been synced to each target album
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Cache {
+    /// Synced checksums with the time they were synced, keyed by the id of the target album
+    albums: HashMap<String, HashMap<String, DateTime<Utc>>>,
+
+    /// State of the pairs of albums after their last complete sync
+    #[serde(default)]
+    pairs: HashMap<String, PairState>,
+
+    /// The file this cache is stored in
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+/// Last modification and number of assets of both albums of a pair after it was fully synced.
+/// If both albums are still in this state, there is nothing to sync.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PairState {
+    pub source_updated_at: DateTime<Utc>,
+    pub source_count: usize,
+    pub target_updated_at: DateTime<Utc>,
+    pub target_count: usize,
+
+    /// Fingerprint of the options that decide which assets are synced
+    pub options: String,
+}
+
+impl PairState {
+    /// The current state of the pair, if both albums report their last modification and number
+    /// of assets
+    pub fn new(source: &Album, target: &Album, options: &str) -> Option<Self> {
+        Some(Self {
+            source_updated_at: source.updated_at?,
+            source_count: source.asset_count?,
+            target_updated_at: target.updated_at?,
+            target_count: target.asset_count?,
+            options: options.to_owned(),
+        })
+    }
+
+    /// Key of the pair of albums
+    pub fn key(source: &Album, target: &Album) -> String {
+        format!("{}>{}", source.id, target.id)
+    }
+}
+
+impl Cache {
+    /// Load the cache from the given file. A missing file results in an empty cache.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut cache: Cache = match fs::read_to_string(path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .with_context(|| format!("Invalid cache file {}", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Cache::default(),
+            Err(e) => return Err(e).context(format!("Reading cache {}", path.display())),
+        };
+        cache.path = path.to_path_buf();
+        Ok(cache)
+    }
+
+    /// Write the cache back to its file
+    pub fn save(&self) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Writing cache {}", self.path.display()))
+    }
+
+    /// Get the checksums that are known to be synced to the given album and are still in its
+    /// listing. Entries whose checksum is missing from the album have been removed from it and
+    /// are dropped from the cache, so the asset is synced again.
+    pub fn synced(&mut self, album: &Album) -> HashSet<String> {
+        let Some(entries) = self.albums.get_mut(&album.id) else {
+            return HashSet::new();
+        };
+        let present: HashSet<_> = album.assets.iter().map(|a| &a.checksum).collect();
+        entries.retain(|checksum, _| present.contains(checksum));
+        entries.keys().cloned().collect()
+    }
+
+    /// Record that the given checksums have been synced to the album with the given id
+    pub fn insert<'a>(&mut self, album_id: &str, checksums: impl IntoIterator<Item = &'a String>) {
+        let now = Utc::now();
+        let entries = self.albums.entry(album_id.to_owned()).or_default();
+        for checksum in checksums {
+            entries.insert(checksum.clone(), now);
+        }
+    }
+
+    /// Remove the entries whose checksum is not in the given set, e.g. because the asset is not
+    /// in any album anymore. Returns the number of removed entries.
+    pub fn prune(&mut self, present: &HashSet<&String>) -> usize {
+        let mut pruned = 0;
+        for entries in self.albums.values_mut() {
+            let before = entries.len();
+            entries.retain(|checksum, _| present.contains(checksum));
+            pruned += before - entries.len();
+        }
+        self.albums.retain(|_, entries| !entries.is_empty());
+        pruned
+    }
+
+    /// States of the pairs of albums after their last complete sync, keyed by `PairState::key`
+    pub fn pairs(&self) -> &HashMap<String, PairState> {
+        &self.pairs
+    }
+
+    /// Record the state of a pair of albums after a complete sync
+    pub fn set_pair(&mut self, key: String, state: PairState) {
+        self.pairs.insert(key, state);
+    }
+}
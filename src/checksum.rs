@@ -0,0 +1,55 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use clap::ValueEnum;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+/// Algorithm of the checksums that the server reports for its assets
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChecksumAlgorithm {
+    /// Used by immich
+    #[default]
+    Sha1,
+    Sha256,
+    Blake3,
+}
+
+impl ChecksumAlgorithm {
+    /// Start computing a checksum with this algorithm
+    pub fn hasher(self) -> Hasher {
+        match self {
+            ChecksumAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+}
+
+/// Computes a checksum incrementally, e.g. while a file is downloaded
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    /// Add the data to the checksum
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+        }
+    }
+
+    /// The checksum of all data, base64 encoded like the checksums reported by immich
+    pub fn finish(self) -> String {
+        match self {
+            Hasher::Sha1(hasher) => BASE64.encode(hasher.finalize()),
+            Hasher::Sha256(hasher) => BASE64.encode(hasher.finalize()),
+            Hasher::Blake3(hasher) => BASE64.encode(hasher.finalize().as_bytes()),
+        }
+    }
+}
@@ -0,0 +1,615 @@
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use clap::ValueEnum;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{error, warn};
+
+/// Type alias to describe the config file
+pub type Config = HashMap<String, Peer>;
+
+/// Formats the config file can be written in
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Detect the format from the extension of the file, defaulting to TOML
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or_default();
+        match extension.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Self::Yaml,
+            "json" => Self::Json,
+            _ => Self::Toml,
+        }
+    }
+}
+
+/// Load and merge the given config files. Directories are replaced by the config files
+/// (.toml, .yaml, .yml, .json) they contain, http(s) URLs are fetched with the client. Every
+/// peer may only be defined in one of the files. The format of each file is detected from its
+/// extension, unless `format` is given.
+pub async fn load(
+    paths: &[PathBuf],
+    format: Option<ConfigFormat>,
+    client: &Client,
+) -> Result<Config> {
+    let mut files = Vec::new();
+    for path in paths {
+        if as_url(path).is_some() {
+            files.push(path.clone());
+        } else if path.is_dir() {
+            let mut entries = Vec::new();
+            for entry in
+                fs::read_dir(path).with_context(|| format!("Reading {}", path.display()))?
+            {
+                let entry = entry?.path();
+                let extension = entry
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or_default();
+                if ["toml", "yaml", "yml", "json"]
+                    .contains(&extension.to_ascii_lowercase().as_str())
+                {
+                    entries.push(entry);
+                }
+            }
+            entries.sort();
+            files.extend(entries);
+        } else {
+            files.push(path.clone());
+        }
+    }
+
+    let mut config = Config::new();
+    let mut origins: HashMap<String, PathBuf> = HashMap::new();
+    for file in files {
+        let (raw, format) = match as_url(&file) {
+            Some(url) => {
+                let format = format.unwrap_or_else(|| ConfigFormat::from_path(url.path().as_ref()));
+                (fetch(&url, client).await?, format)
+            }
+            None => {
+                let raw = fs::read_to_string(&file)
+                    .with_context(|| format!("Reading {}", file.display()))?;
+                (
+                    raw,
+                    format.unwrap_or_else(|| ConfigFormat::from_path(&file)),
+                )
+            }
+        };
+        let peers = parse(&raw, format)
+            .with_context(|| format!("Invalid config file {}", file.display()))?;
+        for (name, peer) in peers {
+            if let Some(other) = origins.get(&name) {
+                bail!(
+                    "Peer {name} is defined in {} and {}",
+                    other.display(),
+                    file.display()
+                );
+            }
+            origins.insert(name.clone(), file.clone());
+            config.insert(name, peer);
+        }
+    }
+    Ok(config)
+}
+
+/// The config path as URL, if it is an http(s) URL
+fn as_url(path: &Path) -> Option<Url> {
+    let path = path.to_str()?;
+    if !(path.starts_with("http://") || path.starts_with("https://")) {
+        return None;
+    }
+    Url::parse(path).ok()
+}
+
+/// Fetch a config from the URL. The last fetched version is kept on disk and used if the
+/// config can't be fetched, so a temporarily unreachable server doesn't stop the syncs. Without
+/// a copy, a failed fetch is an error.
+async fn fetch(url: &Url, client: &Client) -> Result<String> {
+    let cached = cached_config_path(url);
+    match fetch_raw(url, client).await {
+        Ok(raw) => {
+            let written = fs::create_dir_all(cached.parent().expect("cache path has a parent"))
+                .and_then(|()| fs::write(&cached, &raw));
+            if let Err(e) = written {
+                warn!(
+                    "Keeping a copy of config {url} in {} failed: {e}",
+                    cached.display()
+                );
+            }
+            Ok(raw)
+        }
+        Err(e) => match fs::read_to_string(&cached) {
+            Ok(raw) => {
+                // The server answered with an error status or can't be reached, the copy may be
+                // outdated
+                error!("{e:#}, using the copy from {}", cached.display());
+                Ok(raw)
+            }
+            Err(_) => Err(e),
+        },
+    }
+}
+
+/// Fetch the config from the URL without falling back to the copy on disk
+async fn fetch_raw(url: &Url, client: &Client) -> Result<String> {
+    let res = client
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("Fetching config {url} failed"))?;
+    if !res.status().is_success() {
+        bail!("Fetching config {url} failed: {}", res.status());
+    }
+    res.text()
+        .await
+        .with_context(|| format!("Fetching config {url} failed"))
+}
+
+/// Where the last fetched version of the config from the URL is kept, in the user's cache
+/// directory
+fn cached_config_path(url: &Url) -> PathBuf {
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+    let extension = Path::new(url.path())
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("toml");
+    // The URL may contain credentials, so only a hash of it is used as file name
+    let name = URL_SAFE_NO_PAD.encode(Sha1::digest(url.as_str()));
+    dir.join("iss").join(format!("config-{name}.{extension}"))
+}
+
+/// Parse the contents of a config file in the given format. References to environment
+/// variables (`${NAME}`) in the album settings are replaced by their values.
+pub fn parse(raw: &str, format: ConfigFormat) -> Result<Config> {
+    let mut config: Config = match format {
+        ConfigFormat::Toml => toml::from_str(raw)?,
+        ConfigFormat::Yaml => serde_yaml::from_str(raw)?,
+        ConfigFormat::Json => serde_json::from_str(raw)?,
+    };
+    for (name, peer) in &mut config {
+        peer.expand_env()
+            .with_context(|| format!("Invalid config of peer {name}"))?;
+    }
+    Ok(config)
+}
+
+/// Build a config that syncs the album of the share link `from` to the album of the share link
+/// `to`, for a single sync without a config file
+pub fn one_shot(from: &str, to: &str) -> Config {
+    let peer = |link: &str, sync_with| Peer {
+        shared_link: Some(OneOrMany::One(link.to_owned())),
+        api_key: None,
+        album_id: None,
+        base_url: None,
+        api_base_url: None,
+        api_path: None,
+        sync_with,
+        exclude: Vec::new(),
+        groups: Vec::new(),
+        concurrency: None,
+    };
+    HashMap::from([
+        ("from".to_owned(), peer(from, Vec::new())),
+        ("to".to_owned(), peer(to, vec!["from".to_owned()])),
+    ])
+}
+
+/// Replace all `${NAME}` references in the value by the environment variable `NAME`
+fn expand_env(value: &mut String) -> Result<()> {
+    if !value.contains("${") {
+        return Ok(());
+    }
+    let mut expanded = String::new();
+    let mut rest = value.as_str();
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let len = rest[start..]
+            .find('}')
+            .context("Unterminated reference to an environment variable")?;
+        let name = &rest[start + 2..start + len];
+        let var =
+            env::var(name).with_context(|| format!("Environment variable {name} is not set"))?;
+        expanded.push_str(&var);
+        rest = &rest[start + len + 1..];
+    }
+    expanded.push_str(rest);
+    *value = expanded;
+    Ok(())
+}
+
+/// Either a single value or a list of values
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    /// All contained values
+    pub fn values(&self) -> Vec<&str> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values.iter().map(String::as_str).collect(),
+        }
+    }
+
+    /// All contained values, mutable
+    fn values_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values.iter_mut().collect(),
+        }
+    }
+}
+
+/// Where the assets of one album of a peer come from
+#[derive(Debug)]
+pub enum AlbumSource<'a> {
+    /// A shared album, accessed via its share link
+    SharedLink {
+        link: &'a str,
+
+        /// Base url for API requests, instead of the one from the link
+        api_base_url: Option<&'a str>,
+
+        /// Path of the API below the base url, instead of /api
+        api_path: Option<&'a str>,
+    },
+
+    /// An album of a user, accessed via an API key
+    ApiKey {
+        base_url: &'a str,
+        api_key: &'a str,
+        album_id: &'a str,
+
+        /// Path of the API below the base url, instead of /api
+        api_path: Option<&'a str>,
+    },
+}
+
+/// Peer entry in the config file. The album is either accessed via `shared_link` or via
+/// `api_key`, `album_id` and `base_url`.
+#[derive(Deserialize, Debug, PartialEq, Eq)]
+pub struct Peer {
+    /// Link to the shared album, or a list of links to multiple shared albums
+    pub shared_link: Option<OneOrMany>,
+
+    /// API key of a user that can access the album
+    pub api_key: Option<String>,
+
+    /// Id of the album, required when using an API key
+    pub album_id: Option<String>,
+
+    /// Base url of the immich instance, required when using an API key
+    pub base_url: Option<String>,
+
+    /// Base url of the immich instance that is used for API requests instead of the one from
+    /// the share link, for setups where the public and the API url differ
+    pub api_base_url: Option<String>,
+
+    /// Path of the immich API below the base url, for reverse proxies that serve it somewhere
+    /// else than /api
+    pub api_path: Option<String>,
+
+    /// List of names of peers that this peer should download its assets from. A name can be
+    /// suffixed with ":both" to also upload the assets of this peer to that peer. "*" stands
+    /// for all other peers except the ones in `exclude`.
+    pub sync_with: Vec<String>,
+
+    /// Peers that are not included in "*" in sync_with
+    #[serde(default)]
+    pub exclude: Vec<String>,
+
+    /// Groups of the peer, to only sync the peers of some groups with --group
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// Number of parallel downloads/uploads for this peer, overrides --concurrency
+    pub concurrency: Option<usize>,
+}
+
+impl Peer {
+    /// Replace references to environment variables in the album settings by their values
+    fn expand_env(&mut self) -> Result<()> {
+        let links = self.shared_link.iter_mut().flat_map(OneOrMany::values_mut);
+        let settings = [
+            &mut self.api_key,
+            &mut self.album_id,
+            &mut self.base_url,
+            &mut self.api_base_url,
+            &mut self.api_path,
+        ];
+        for value in links.chain(settings.into_iter().flatten()) {
+            expand_env(value)?;
+        }
+        Ok(())
+    }
+
+    /// Get the sources of all albums of this peer
+    pub fn albums(&self) -> Result<Vec<AlbumSource<'_>>> {
+        match (
+            &self.shared_link,
+            &self.api_key,
+            &self.album_id,
+            &self.base_url,
+        ) {
+            (Some(shared_links), None, None, None) => Ok(shared_links
+                .values()
+                .into_iter()
+                .map(|link| AlbumSource::SharedLink {
+                    link,
+                    api_base_url: self.api_base_url.as_deref(),
+                    api_path: self.api_path.as_deref(),
+                })
+                .collect()),
+            (None, Some(_), Some(_), Some(_)) if self.api_base_url.is_some() => {
+                bail!("api_base_url can only be used with shared_link, use base_url instead")
+            }
+            (None, Some(api_key), Some(album_id), Some(base_url)) => {
+                Ok(vec![AlbumSource::ApiKey {
+                    base_url,
+                    api_key,
+                    album_id,
+                    api_path: self.api_path.as_deref(),
+                }])
+            }
+            _ => bail!("Either shared_link or api_key, album_id and base_url have to be set"),
+        }
+    }
+}
+
+/// Split a share link (e.g. `https://immich.example.org/share/key`) into the base url of the
+/// immich instance and the key of the link. Anything after the key, the query and the fragment
+/// are ignored.
+pub fn parse_share_link(link: &str) -> Result<(String, String)> {
+    let url = Url::parse(link).with_context(|| format!("{link} is not a valid URL"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        bail!("{link} is not an http or https URL");
+    }
+    let Some((prefix, key)) = url.path().split_once("/share/") else {
+        bail!("{link} does not contain /share/");
+    };
+    // Only the first segment is the key, the web UI appends e.g. /photos/<id> for a single photo
+    let key = key.split('/').next().unwrap_or_default();
+    if key.is_empty() {
+        bail!("{link} does not contain a key after /share/");
+    }
+
+    let origin = url.origin().ascii_serialization();
+    Ok((format!("{origin}{prefix}"), key.to_owned()))
+}
+
+/// Get the pairs of album indices that are synced when a peer with `from` albums syncs to a peer
+/// with `to` albums. Albums are paired by their position, a single album is paired with all
+/// albums of the other peer.
+pub fn album_pairs(from: usize, to: usize) -> Result<Vec<(usize, usize)>> {
+    if from == to {
+        Ok((0..from).map(|i| (i, i)).collect())
+    } else if from == 1 {
+        Ok((0..to).map(|i| (0, i)).collect())
+    } else if to == 1 {
+        Ok((0..from).map(|i| (i, 0)).collect())
+    } else {
+        bail!("Can not pair {from} albums with {to} albums")
+    }
+}
+
+/// Split a sync_with entry into the peer name and whether it should be synced in both directions
+pub fn parse_sync_target(entry: &str) -> (&str, bool) {
+    match entry.strip_suffix(":both") {
+        Some(name) => (name, true),
+        None => (entry, false),
+    }
+}
+
+/// Replace the "*" entries of sync_with by all other peers that are not excluded and not
+/// already listed explicitly
+pub fn expand_wildcards(config: &mut Config) -> Result<()> {
+    let names: BTreeSet<String> = config.keys().cloned().collect();
+    for (name, peer) in config.iter_mut() {
+        if let Some(unknown) = peer.exclude.iter().find(|e| !names.contains(*e)) {
+            bail!("Peer {name} excludes unknown peer {unknown}");
+        }
+        let wildcard = peer
+            .sync_with
+            .iter()
+            .find(|e| parse_sync_target(e).0 == "*");
+        let Some((_, both)) = wildcard.map(|entry| parse_sync_target(entry)) else {
+            continue;
+        };
+        let listed: HashSet<String> = peer
+            .sync_with
+            .iter()
+            .map(|entry| parse_sync_target(entry).0.to_owned())
+            .collect();
+        let others = names
+            .iter()
+            .filter(|other| *other != name && !listed.contains(*other))
+            .filter(|other| !peer.exclude.contains(other))
+            .map(|other| {
+                if both {
+                    format!("{other}:both")
+                } else {
+                    other.clone()
+                }
+            });
+        let mut sync_with: Vec<String> = peer
+            .sync_with
+            .iter()
+            .filter(|entry| parse_sync_target(entry).0 != "*")
+            .cloned()
+            .collect();
+        sync_with.extend(others);
+        peer.sync_with = sync_with;
+    }
+    Ok(())
+}
+
+/// Restrict the config to the peers that are in one of the given groups. Their sync_with entries
+/// for peers outside of the groups are dropped.
+pub fn select_groups(config: &mut Config, groups: &[String]) -> Result<()> {
+    for group in groups {
+        if !config.values().any(|peer| peer.groups.contains(group)) {
+            bail!("No peer is in group {group}");
+        }
+    }
+    config.retain(|_, peer| peer.groups.iter().any(|g| groups.contains(g)));
+    let names: HashSet<String> = config.keys().cloned().collect();
+    for peer in config.values_mut() {
+        peer.sync_with
+            .retain(|entry| names.contains(parse_sync_target(entry).0));
+    }
+    Ok(())
+}
+
+/// Get all sync_with entries as pairs of the peer name and the entry, ordered so that a peer only
+/// receives assets from another peer after that peer received its own assets. This way a single
+/// run propagates assets through a chain of peers. Entries that sync in both directions (with
+/// `:both` or if `bidirectional` is set) also deliver assets to the other peer, so peers that
+/// receive from it wait for them as well. Peers that are part of a cycle are synced after all
+/// other peers, in alphabetical order.
+pub fn sync_order(config: &Config, bidirectional: bool) -> Vec<(&str, &str)> {
+    // Peers whose entries deliver assets to each peer in both directions
+    let mut both_ways: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (name, peer) in config {
+        for entry in &peer.sync_with {
+            let (other_name, both) = parse_sync_target(entry);
+            if both || bidirectional {
+                both_ways.entry(other_name).or_default().push(name);
+            }
+        }
+    }
+    // Number of peers whose entries have to be synced before the entries of each peer
+    let mut pending: BTreeMap<&str, usize> = config.keys().map(|n| (n.as_str(), 0)).collect();
+    let mut receivers: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (name, peer) in config {
+        // The peers an entry receives from must have received all their assets, except the ones
+        // this peer delivers itself
+        let mut dependencies: Vec<&str> = peer
+            .sync_with
+            .iter()
+            .flat_map(|entry| {
+                let (other_name, _) = parse_sync_target(entry);
+                let senders = both_ways.get(other_name).into_iter().flatten().copied();
+                std::iter::once(other_name).chain(senders)
+            })
+            .filter(|dependency| *dependency != name.as_str())
+            .collect();
+        dependencies.sort();
+        dependencies.dedup();
+        for dependency in dependencies {
+            *pending.entry(name).or_default() += 1;
+            receivers.entry(dependency).or_default().push(name);
+        }
+    }
+
+    let mut order = Vec::new();
+    let mut ready: BTreeSet<&str> = pending
+        .iter()
+        .filter(|(_, n)| **n == 0)
+        .map(|(name, _)| *name)
+        .collect();
+    while let Some(name) = ready.pop_first() {
+        pending.remove(name);
+        order.push(name);
+        for receiver in receivers.get(name).into_iter().flatten() {
+            if let Some(n) = pending.get_mut(receiver) {
+                *n -= 1;
+                if *n == 0 {
+                    ready.insert(receiver);
+                }
+            }
+        }
+    }
+    if !pending.is_empty() {
+        let cycle: Vec<_> = pending.keys().copied().collect();
+        warn!(
+            "The sync_with entries of {} form a cycle, they are synced in alphabetical order and \
+             may need multiple runs to sync all assets",
+            cycle.join(", ")
+        );
+        order.extend(cycle);
+    }
+
+    order
+        .into_iter()
+        .flat_map(|name| {
+            let mut entries: Vec<_> = config[name].sync_with.iter().map(String::as_str).collect();
+            entries.sort();
+            entries.into_iter().map(move |entry| (name, entry))
+        })
+        .collect()
+}
+
+/// Check the config for invalid values and references to unknown peers. Pairs that would be
+/// synced more than once in the same direction are reported as warnings.
+pub fn validate(config: &Config, bidirectional: bool) -> Result<()> {
+    let mut directions: HashMap<(&str, &str), usize> = HashMap::new();
+    for (name, peer) in config {
+        if peer.concurrency == Some(0) {
+            bail!("concurrency of peer {name} must be at least 1");
+        }
+        let albums = peer
+            .albums()
+            .with_context(|| format!("Invalid peer {name}"))?;
+        for album in &albums {
+            if let AlbumSource::SharedLink { link, .. } = album {
+                parse_share_link(link)
+                    .with_context(|| format!("Invalid share link of peer {name}"))?;
+            }
+        }
+        if let Some(api_base_url) = &peer.api_base_url {
+            Url::parse(api_base_url)
+                .with_context(|| format!("Invalid api_base_url of peer {name}"))?;
+        }
+        for entry in &peer.sync_with {
+            let (other_name, both) = parse_sync_target(entry);
+            let Some(other) = config.get(other_name) else {
+                bail!("Peer {name} syncs with unknown peer {other_name}");
+            };
+            if other_name == name {
+                bail!("Peer {name} can not sync with itself");
+            }
+            let other_albums = other
+                .albums()
+                .with_context(|| format!("Invalid peer {other_name}"))?;
+            album_pairs(other_albums.len(), albums.len()).with_context(|| {
+                format!("Peers {name} and {other_name} need the same number of albums or one album")
+            })?;
+            *directions.entry((other_name, name)).or_default() += 1;
+            if both || bidirectional {
+                *directions.entry((name, other_name)).or_default() += 1;
+            }
+        }
+    }
+
+    let mut redundant: Vec<_> = directions.iter().filter(|(_, n)| **n > 1).collect();
+    redundant.sort();
+    for ((from, to), _) in redundant {
+        warn!(
+            "Assets from {from} to {to} are synced multiple times, \
+             because {from} and {to} reference each other"
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use crate::progress;
+use anyhow::{Context, Result, bail};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::sync::Mutex;
+
+/// Only one question is asked at a time, even if multiple pairs are synced in parallel
+static PROMPT: Mutex<()> = Mutex::new(());
+
+/// Ask the user on the terminal whether to go ahead with the operation described by
+/// `question`. Without a terminal nobody can answer, so this fails and asks for --yes.
+pub async fn ask(question: String) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        bail!("{question}? Pass --yes to confirm this when not running in a terminal");
+    }
+    tokio::task::spawn_blocking(move || {
+        let _prompt = PROMPT.lock().unwrap();
+        progress::suspend(|| {
+            eprint!("{question}? [y/N] ");
+            io::stderr().flush()?;
+            let mut answer = String::new();
+            io::stdin().lock().read_line(&mut answer)?;
+            Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+        })
+    })
+    .await
+    .context("Asking for confirmation failed")?
+}
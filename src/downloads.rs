@@ -0,0 +1,63 @@
+use crate::DownloadDir;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::OnceCell;
+
+/// Files that have been downloaded during the current run, keyed by their checksum, so an asset
+/// that is missing in several albums is only downloaded once. Concurrent downloads of the same
+/// checksum wait for the first one instead of downloading the file again.
+#[derive(Debug, Default)]
+pub struct Downloads {
+    files: Mutex<HashMap<String, Arc<OnceCell<PathBuf>>>>,
+
+    /// Download directories whose files may be reused, temporary ones are deleted by `clear`
+    dirs: Mutex<Vec<DownloadDir>>,
+}
+
+impl Downloads {
+    /// Get the file with the given checksum, downloading it with `download` if it has not been
+    /// downloaded during this run yet. Returns the path and whether it was downloaded now. A
+    /// failed download is tried again by the next asset with the same checksum.
+    pub async fn get_or_download<F, Fut>(
+        &self,
+        checksum: &str,
+        download: F,
+    ) -> Result<(PathBuf, bool)>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<PathBuf>>,
+    {
+        let cell = self
+            .files
+            .lock()
+            .unwrap()
+            .entry(checksum.to_owned())
+            .or_default()
+            .clone();
+        let mut downloaded = false;
+        let path = cell
+            .get_or_try_init(|| {
+                downloaded = true;
+                download()
+            })
+            .await?;
+        Ok((path.clone(), downloaded))
+    }
+
+    /// Keep the download directory until the end of the run, so its files can be reused even if
+    /// the sync that downloaded them fails. Returns the path of the directory.
+    pub fn keep(&self, dir: DownloadDir) -> PathBuf {
+        let path = dir.path().to_path_buf();
+        self.dirs.lock().unwrap().push(dir);
+        path
+    }
+
+    /// Forget all downloads and delete the temporary download directories, e.g. before the next
+    /// run, where the files may have changed
+    pub fn clear(&self) {
+        self.files.lock().unwrap().clear();
+        self.dirs.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,83 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Failures that callers may want to handle differently, e.g. to skip a peer or to retry an
+/// asset later. They are returned inside an `anyhow::Error` and can be told apart with
+/// `downcast_ref::<IssError>()`.
+#[derive(Error, Debug)]
+pub enum IssError {
+    /// The share link of a peer can't be parsed
+    #[error("Invalid share link of peer {peer}")]
+    InvalidShareLink {
+        peer: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// The server does not accept the share link anymore
+    #[error("Share link for peer '{peer}' is expired or revoked")]
+    DeadLink { peer: String },
+
+    /// The album does not exist or can't be accessed with the API key
+    #[error("Album {album_id} of peer '{peer}' was not found")]
+    AlbumNotFound { album_id: String, peer: String },
+
+    /// The server did not return the file of an asset
+    #[error("Download failed for {asset} from {album}: {status}")]
+    DownloadFailed {
+        asset: String,
+        album: String,
+        status: StatusCode,
+    },
+
+    /// The server returned something else than a media file, e.g. a login page
+    #[error(
+        "Download failed for {asset} from {album}: got {content_type} instead of the file, \
+         the share link may have expired"
+    )]
+    NotAFile {
+        asset: String,
+        album: String,
+        content_type: String,
+    },
+
+    /// The downloaded file does not have the checksum of the asset
+    #[error("Checksum mismatch for {asset} from {album}: expected {expected}, got {actual}")]
+    ChecksumMismatch {
+        asset: String,
+        album: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// The server rejected the upload of an asset
+    #[error("Upload of {asset} to {album} failed with status {status}: {body}")]
+    UploadFailed {
+        asset: String,
+        album: String,
+        status: StatusCode,
+        body: String,
+    },
+
+    /// The uploaded asset does not have the checksum of the source asset
+    #[error(
+        "Checksum mismatch after upload of {asset} to {album}: expected {expected}, got {actual}"
+    )]
+    UploadChecksumMismatch {
+        asset: String,
+        album: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// The album can't be used as target
+    #[error("Target album {album} of peer '{peer}' does not allow uploads")]
+    UploadNotAllowed { album: String, peer: String },
+
+    /// Some assets failed to sync, the others were synced
+    #[error("{count} assets failed to sync")]
+    AssetsFailed { count: usize },
+
+    /// Some sync_with entries were skipped because the share link of their peer is dead
+    #[error("{count} sync_with entries were skipped because of dead share links")]
+    EntriesSkipped { count: usize },
+}
@@ -0,0 +1,176 @@
+use crate::{Asset, AssetType};
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeDelta, Utc};
+use clap::ValueEnum;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+/// Kinds of assets that can be selected on the command line
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaType {
+    Images,
+    Videos,
+}
+
+impl MediaType {
+    /// Whether an asset of the given type is of this kind
+    fn contains(self, asset_type: AssetType) -> bool {
+        match self {
+            MediaType::Images => asset_type == AssetType::Image,
+            MediaType::Videos => asset_type == AssetType::Video,
+        }
+    }
+}
+
+/// Criteria that assets have to match to be synced
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Only assets created at or after this time are synced
+    pub since: Option<DateTime<Utc>>,
+
+    /// Only assets created before this time are synced
+    pub until: Option<DateTime<Utc>>,
+
+    /// Only assets of this kind are synced
+    pub only: Option<MediaType>,
+
+    /// Assets of these kinds are not synced
+    pub skip: Vec<MediaType>,
+
+    /// If not empty, only assets with a matching file name are synced
+    pub include: GlobSet,
+
+    /// Assets with a matching file name are not synced, even if they match `include`
+    pub exclude: GlobSet,
+
+    /// Only assets with at most this size in bytes are synced
+    pub max_size: Option<u64>,
+
+    /// Only assets with at least this size in bytes are synced
+    pub min_size: Option<u64>,
+
+    /// Archived assets are synced as well
+    pub include_archived: bool,
+}
+
+impl Filter {
+    /// Whether the asset should be synced
+    pub fn matches(&self, asset: &Asset) -> bool {
+        if asset.is_trashed || (asset.archived() && !self.include_archived) {
+            return false;
+        }
+        if self
+            .only
+            .is_some_and(|only| !only.contains(asset.asset_type))
+            || self.skip.iter().any(|skip| skip.contains(asset.asset_type))
+        {
+            return false;
+        }
+        if self.exclude.is_match(&asset.file_name)
+            || !(self.include.is_empty() || self.include.is_match(&asset.file_name))
+        {
+            return false;
+        }
+        if self.outside_size_limits(asset) {
+            return false;
+        }
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        let created = asset
+            .file_created_at
+            .as_deref()
+            .map(DateTime::parse_from_rfc3339);
+        let Some(Ok(created)) = created else {
+            return false;
+        };
+        self.since.is_none_or(|since| created >= since)
+            && self.until.is_none_or(|until| created < until)
+    }
+
+    /// Whether the asset is larger than `max_size` or smaller than `min_size`. Assets whose size
+    /// is not known are not excluded.
+    pub fn outside_size_limits(&self, asset: &Asset) -> bool {
+        let Some(size) = asset.size() else {
+            return false;
+        };
+        self.max_size.is_some_and(|max| size > max) || self.min_size.is_some_and(|min| size < min)
+    }
+}
+
+/// Parse a file size like `500MB`, `1.5GiB` or `1024`. Units without `i` are decimal (1 KB =
+/// 1000 bytes), units with `i` are binary (1 KiB = 1024 bytes), plain numbers are bytes.
+pub fn parse_size(input: &str) -> Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let factor: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1000,
+        "m" | "mb" => 1000_u64.pow(2),
+        "g" | "gb" => 1000_u64.pow(3),
+        "t" | "tb" => 1000_u64.pow(4),
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => {
+            return Err(format!(
+                "invalid unit '{unit}', expected e.g. 500MB or 2GiB"
+            ));
+        }
+    };
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size '{input}', expected e.g. 500MB or 2GiB"))?;
+    Ok((number * factor as f64) as u64)
+}
+
+/// Build a case insensitive set of the given glob patterns
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()
+            .with_context(|| format!("Invalid glob pattern {pattern}"))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+/// Parse a date (`2024-06-30`) or a date and time (`2024-06-30T12:00:00`, optionally with
+/// offset) into the start of the given date or the given time. Times without offset are UTC.
+fn parse_date(input: &str) -> Result<(DateTime<Utc>, bool), String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(input) {
+        return Ok((time.to_utc(), false));
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M"] {
+        if let Ok(time) = NaiveDateTime::parse_from_str(input, format) {
+            return Ok((time.and_utc(), false));
+        }
+    }
+    match NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+        Ok(date) => Ok((date.and_time(Default::default()).and_utc(), true)),
+        Err(_) => Err(format!(
+            "invalid date '{input}', expected e.g. 2024-06-30 or 2024-06-30T12:00:00"
+        )),
+    }
+}
+
+/// Parse the argument of --since
+pub fn parse_since(input: &str) -> Result<DateTime<Utc>, String> {
+    parse_date(input).map(|(time, _)| time)
+}
+
+/// Parse the argument of --until. A date without time includes the whole day.
+pub fn parse_until(input: &str) -> Result<DateTime<Utc>, String> {
+    parse_date(input).map(|(time, date_only)| {
+        if date_only {
+            time + TimeDelta::days(1)
+        } else {
+            time
+        }
+    })
+}
@@ -1,51 +1,611 @@
-use anyhow::{Context, Result, bail};
-use clap::Parser;
-use futures::{StreamExt, TryStreamExt, stream};
-use reqwest::Client;
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use cache::{Cache, PairState};
+use checksum::ChecksumAlgorithm;
+use chrono::{DateTime, Utc};
+use clap::{Parser, ValueEnum};
+use config::{AlbumSource, Config, ConfigFormat, album_pairs, parse_sync_target};
+use downloads::Downloads;
+use error::IssError;
+use filetime::FileTime;
+use filter::{Filter, MediaType};
+use futures::{StreamExt, stream};
+use indicatif::HumanBytes;
+use plan::{Plan, PlannedPair};
+use progress::{BatchProgress, Progress, Transfer};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::multipart::{Form, Part};
+use reqwest::{Body, Certificate, Client, Method, Proxy, RequestBuilder, Response, StatusCode};
 use serde::Deserialize;
+use sha1::{Digest, Sha1};
+use state::State;
 use std::collections::{HashMap, HashSet};
+use std::env;
 use std::fs;
-use std::fs::File;
-use std::io::Write;
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use summary::{OutputFormat, PairSummary, RunSummary, SkippedAsset};
+use tempfile::{TempDir, TempPath};
+use tokio::io::AsyncWriteExt;
+use tracing::{Instrument, Level, debug, error, info, info_span, instrument, warn};
 
-/// Type alias to describe the config file
-type Config = HashMap<String, Peer>;
+mod cache;
+mod checksum;
+mod config;
+mod confirm;
+mod downloads;
+mod error;
+mod filter;
+mod metrics;
+mod notify;
+mod plan;
+mod progress;
+mod ratelimit;
+mod report;
+mod retry;
+mod state;
+mod stats;
+mod summary;
+mod tus;
 
-/// Peer entry in the config file
-#[derive(Deserialize, Debug)]
-struct Peer {
-    /// Link to the shared album
-    shared_link: String,
+/// Number of assets that are requested per page when listing an album
+const PAGE_SIZE: usize = 1000;
 
-    /// List of names of peers that this peer should download its assets from
-    sync_with: Vec<String>,
-}
+/// Smallest file that is uploaded with a resumable upload with --resumable
+const RESUMABLE_MIN_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Path of the API for resumable uploads
+const RESUMABLE_UPLOAD_PATH: &str = "upload";
+
+/// Error message when a sync is interrupted by Ctrl-C. Interrupting drops the running sync, which
+/// cancels all transfers and removes their temporary directories.
+const INTERRUPTED: &str = "Interrupted, the remaining assets will be synced by the next run";
+
+/// An asset that could not be synced, together with the reason
+type Failure = (Asset, anyhow::Error);
 
 /// Command line arguments to be parsed by clap
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Path to the config file
-    #[arg(short, long)]
-    config: String,
+    /// Path or http(s) URL of the config file, or a directory of config files. Can be given
+    /// multiple times, each peer may only be defined once.
+    #[arg(short, long, required_unless_present = "from", conflicts_with_all = ["from", "to"])]
+    config: Vec<PathBuf>,
+
+    /// Share link of an album to copy the assets from, together with --to instead of a config
+    /// file
+    #[arg(long, value_name = "SHARE_LINK", requires = "to")]
+    from: Option<String>,
+
+    /// Share link of the album that receives the assets of --from
+    #[arg(long, value_name = "SHARE_LINK", requires = "from")]
+    to: Option<String>,
+
+    /// Format of the config file, detected from its extension (.toml, .yaml/.yml, .json) if
+    /// not given. Files with other extensions are read as TOML.
+    #[arg(long, value_enum)]
+    config_format: Option<ConfigFormat>,
+
+    /// Only sync the peers of this group, with the peers of the same group. Can be given
+    /// multiple times to sync the peers of all given groups.
+    #[arg(long, conflicts_with_all = ["from", "to"])]
+    group: Vec<String>,
 
     /// Only print missing assets
     #[arg(short, long, default_value_t = false)]
     dry_run: bool,
+
+    /// Only print the assets that are only in one of the albums of each sync_with entry, for
+    /// both directions, without syncing anything
+    #[arg(long, default_value_t = false)]
+    dry_run_diff: bool,
+
+    /// Write the assets a dry run would sync to this file, so they can be reviewed and synced
+    /// later with --apply-plan
+    #[arg(long, value_name = "PATH", requires = "dry_run")]
+    plan_out: Option<PathBuf>,
+
+    /// Only sync the assets of a plan written with --plan-out. Assets that changed or are not
+    /// in their source album anymore are skipped.
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["dry_run", "dry_run_diff", "plan_out", "mirror", "interval"]
+    )]
+    apply_plan: Option<PathBuf>,
+
+    /// Number of assets that are downloaded or uploaded in parallel
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Number of assets that are downloaded in parallel, overrides --concurrency and the
+    /// concurrency of the peers
+    #[arg(long)]
+    download_concurrency: Option<usize>,
+
+    /// Number of assets that are uploaded in parallel, overrides --concurrency and the
+    /// concurrency of the peers
+    #[arg(long)]
+    upload_concurrency: Option<usize>,
+
+    /// Maximum number of attempts for each download or upload
+    #[arg(long, default_value_t = 3)]
+    retries: u32,
+
+    /// Sync every sync_with entry in both directions
+    #[arg(short, long, default_value_t = false)]
+    bidirectional: bool,
+
+    /// How assets are identified to find the ones that are missing in the target. filename
+    /// and filename+date also match re-encoded copies, but treat different photos with the
+    /// same name as the same asset.
+    #[arg(long, value_enum, default_value_t)]
+    match_by: MatchBy,
+
+    /// Also treat assets as the same that have a different checksum, but the same file name and
+    /// creation time as an asset of the target, e.g. because the target re-encodes uploads
+    #[arg(long, default_value_t = false)]
+    skip_existing_by_name: bool,
+
+    /// Version of the assets that is synced. preview and thumbnail are much smaller, but lose
+    /// most of the metadata of the originals, so don't use them for backups.
+    #[arg(long, value_enum, default_value_t)]
+    quality: Quality,
+
+    /// Fetch every uploaded asset from the target and compare its checksum to the source, which
+    /// costs an extra request per asset
+    #[arg(long, default_value_t = false)]
+    verify_upload: bool,
+
+    /// Skip verifying the checksums of downloaded assets
+    #[arg(long, default_value_t = false)]
+    no_verify: bool,
+
+    /// Algorithm of the checksums reported by the servers, for instances that don't use SHA-1
+    #[arg(long, value_enum, default_value_t)]
+    checksum_algorithm: ChecksumAlgorithm,
+
+    /// Don't ask the target which assets already exist in its library before downloading them.
+    /// Needed for instances without the bulk upload check endpoint.
+    #[arg(long, default_value_t = false)]
+    no_bulk_check: bool,
+
+    /// Always list and compare the assets of both albums, even if their number of assets and
+    /// last modification are the same as after the last complete sync
+    #[arg(long, default_value_t = false)]
+    no_quick_check: bool,
+
+    /// Path to a file that remembers which assets have already been synced
+    #[arg(long)]
+    cache: Option<PathBuf>,
+
+    /// Remove the entries from the cache whose checksum is not in any configured album anymore,
+    /// without syncing anything
+    #[arg(long, default_value_t = false, requires = "cache")]
+    prune_cache: bool,
+
+    /// Path to a file that records the progress of a run, so it can be resumed after it was
+    /// interrupted. The file is removed when the run completes without failures.
+    #[arg(long)]
+    state: Option<PathBuf>,
+
+    /// Keep syncing the remaining assets when an asset fails and report all failures at the end
+    #[arg(long, default_value_t = false)]
+    continue_on_error: bool,
+
+    /// Print more details, can be given twice for even more output
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Only print warnings, errors and a summary of every pair at the end of a run, without
+    /// progress bars and messages about single assets
+    #[arg(short, long, alias = "summary-only", conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Keep downloaded assets in this directory, in a subdirectory per album, instead of a
+    /// temporary directory that is deleted after syncing
+    #[arg(long, alias = "keep-downloads")]
+    download_dir: Option<PathBuf>,
+
+    /// Stream downloads directly into the uploads instead of storing them on disk first. Only
+    /// used with --no-verify, since the checksums can't be verified before uploading.
+    #[arg(long, default_value_t = false, conflicts_with = "download_dir")]
+    pipe: bool,
+
+    /// Upload files of 64 MiB and more with resumable (tus) uploads if the target supports
+    /// them, so an interrupted upload continues where it stopped instead of starting over
+    #[arg(long, default_value_t = false)]
+    resumable: bool,
+
+    /// Directory in which the temporary download directories are created, instead of the
+    /// system's temporary directory
+    #[arg(long, conflicts_with = "download_dir")]
+    temp_dir: Option<PathBuf>,
+
+    /// Idle timeout: seconds without receiving any data from a server, e.g. while waiting for
+    /// the response or the next chunk of a download, after which a request is aborted. Requests
+    /// that keep receiving data, like long downloads, are not limited.
+    #[arg(long, default_value_t = 300)]
+    timeout: u64,
+
+    /// Seconds after which connecting to a server is aborted
+    #[arg(long, default_value_t = 30)]
+    connect_timeout: u64,
+
+    /// Only sync assets created at or after this date or time (e.g. 2024-06-01)
+    #[arg(long, value_parser = filter::parse_since)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only sync assets created before this date or time, a date includes the whole day
+    #[arg(long, value_parser = filter::parse_until)]
+    until: Option<DateTime<Utc>>,
+
+    /// Only sync assets of this type
+    #[arg(long, value_enum)]
+    only: Option<MediaType>,
+
+    /// Don't sync assets of this type, can be given multiple times
+    #[arg(long, value_enum)]
+    skip: Vec<MediaType>,
+
+    /// Only sync assets whose file name matches this glob pattern (e.g. "*.dng"), can be given
+    /// multiple times. Patterns are case insensitive.
+    #[arg(long)]
+    include: Vec<String>,
+
+    /// Don't sync assets whose file name matches this glob pattern (e.g. "Screenshot_*"), can
+    /// be given multiple times. Takes precedence over --include.
+    #[arg(long)]
+    exclude: Vec<String>,
+
+    /// Don't sync assets larger than this (e.g. 500MB or 2GiB). Assets whose size is not
+    /// reported by immich are synced.
+    #[arg(long, value_parser = filter::parse_size)]
+    max_file_size: Option<u64>,
+
+    /// Don't sync assets smaller than this (e.g. 20KB)
+    #[arg(long, value_parser = filter::parse_size)]
+    min_file_size: Option<u64>,
+
+    /// Also sync assets that their owner archived. Assets in the trash are never synced.
+    #[arg(long, default_value_t = false)]
+    include_archived: bool,
+
+    /// Sync at most this many assets per pair of albums, the remaining assets are synced by
+    /// the next runs
+    #[arg(long, value_name = "N")]
+    limit: Option<usize>,
+
+    /// Order in which the missing assets are synced and added to the album, by creation time
+    #[arg(long, value_enum, default_value_t)]
+    order: SyncOrder,
+
+    /// Number of sync_with entries that are synced in parallel
+    #[arg(long, default_value_t = 1)]
+    peer_concurrency: usize,
+
+    /// Format of the output, json prints a summary of every synced pair of albums at the end
+    #[arg(long, value_enum, default_value_t)]
+    output: OutputFormat,
+
+    /// Remove assets from the target album that are not in the source album. The assets are only
+    /// removed from the album, not deleted from the library.
+    #[arg(long, default_value_t = false)]
+    mirror: bool,
+
+    /// Upload the missing assets into the library of the target without adding them to the
+    /// target album. Requires targets that are accessed with an API key.
+    #[arg(long, default_value_t = false, conflicts_with = "mirror")]
+    no_add_to_album: bool,
+
+    /// Set the description of the target albums to the one of their source album. Requires
+    /// targets that are accessed with an API key, share links can't edit albums.
+    #[arg(long, default_value_t = false)]
+    sync_album_metadata: bool,
+
+    /// Don't ask for confirmation before syncing many assets or removing assets with --mirror.
+    /// Required when not running in a terminal.
+    #[arg(short, long, default_value_t = false)]
+    yes: bool,
+
+    /// Maximum number of assets that are added to an album with a single request
+    #[arg(long, default_value_t = 500, value_name = "ASSETS")]
+    album_batch_size: usize,
+
+    /// Number of assets of a pair above which a sync has to be confirmed
+    #[arg(long, default_value_t = 1000, value_name = "ASSETS")]
+    confirm_above: usize,
+
+    /// Upload all assets of the source albums, even if they already are in the target album or
+    /// the cache, e.g. to replace corrupt copies. Implies --no-bulk-check and --no-quick-check.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+
+    /// Additional root certificate (PEM or DER) to trust, e.g. of a private CA
+    #[arg(long)]
+    ca_cert: Option<PathBuf>,
+
+    /// Accept any TLS certificate, including self-signed and expired ones. This makes the
+    /// connections vulnerable to man-in-the-middle attacks.
+    #[arg(long, default_value_t = false)]
+    danger_accept_invalid_certs: bool,
+
+    /// Append a report of every run to this file, as JSON lines if the file name ends with
+    /// .jsonl or .json and as text otherwise
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Write metrics of every run to this file in the Prometheus text format, e.g. for the
+    /// textfile collector of the node exporter
+    #[arg(long)]
+    metrics_file: Option<PathBuf>,
+
+    /// Send a notification with the result of every run to this webhook. ntfy URLs (e.g.
+    /// https://ntfy.sh/topic) get a message with title and priority, other URLs a JSON object.
+    #[arg(long, value_name = "URL")]
+    notify: Option<String>,
+
+    /// Print the number of requests, transferred bytes, throughput and the slowest transfer at
+    /// the end of a run, also with --quiet. With --output json they are printed to stderr.
+    #[arg(long, default_value_t = false)]
+    stats: bool,
+
+    /// Send all requests through this proxy (e.g. http://proxy:3128 or socks5://proxy:1080).
+    /// Takes precedence over the HTTP_PROXY, HTTPS_PROXY and ALL_PROXY environment variables.
+    #[arg(long)]
+    proxy: Option<String>,
+
+    /// Only print the albums of this peer with the number and size of their assets, without
+    /// syncing anything
+    #[arg(long, value_name = "PEER")]
+    list: Option<String>,
+
+    /// Also print the file name and checksum of every asset with --list
+    #[arg(long, default_value_t = false, requires = "list")]
+    list_assets: bool,
+
+    /// User agent that is sent with every request
+    #[arg(long, default_value = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")))]
+    user_agent: String,
+
+    /// Maximum number of requests per second, over all servers
+    #[arg(long)]
+    rate_limit: Option<f64>,
+
+    /// Keep running and sync again after this time (e.g. "30m" or "6h"). SIGTERM stops after
+    /// the current sync.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    interval: Option<Duration>,
+
+    /// List the assets that are skipped because an asset with the same checksum already is in
+    /// the target album, together with the file name of the existing asset
+    #[arg(long, default_value_t = false)]
+    report_skipped: bool,
+}
+
+/// Options that control how the assets of two albums are synced
+#[derive(Debug)]
+struct SyncOptions {
+    /// Only print missing assets
+    dry_run: bool,
+
+    /// Maximum number of attempts for each download or upload
+    retries: u32,
+
+    /// Compare the checksums of downloaded assets with the ones reported by immich
+    verify: bool,
+
+    /// Compare the checksums of uploaded assets on the target with the source
+    verify_upload: bool,
+
+    /// Stream downloads into the uploads without storing them on disk
+    pipe: bool,
+
+    /// Files of at least this size are uploaded with resumable uploads, if the target supports
+    /// them
+    resumable_min_size: Option<u64>,
+
+    /// Algorithm of the checksums of the assets
+    checksum_algorithm: ChecksumAlgorithm,
+
+    /// What makes an asset of the source the same as an asset of the target
+    match_by: MatchBy,
+
+    /// Also treat assets with the same file name and creation time as the same
+    skip_existing_by_name: bool,
+
+    /// Version of the assets that is downloaded
+    quality: Quality,
+
+    /// Number of parallel downloads, instead of the concurrency of the source peer
+    download_concurrency: Option<usize>,
+
+    /// Number of parallel uploads, instead of the concurrency of the target peer
+    upload_concurrency: Option<usize>,
+
+    /// Link assets that already exist in the library of the target instead of downloading them
+    bulk_check: bool,
+
+    /// Skip pairs of albums that did not change since their last complete sync
+    quick_check: bool,
+
+    /// Collect failed assets instead of aborting on the first failure
+    continue_on_error: bool,
+
+    /// Criteria that assets have to match to be synced
+    filter: Filter,
+
+    /// Format of the output
+    output: OutputFormat,
+
+    /// Only print the summary at the end, not the assets that would be synced or removed
+    quiet: bool,
+
+    /// Remove assets from the target album that are not in the source album
+    mirror: bool,
+
+    /// Set the description of the target album to the one of the source album
+    sync_album_metadata: bool,
+
+    /// Add the uploaded assets to the target album
+    add_to_album: bool,
+
+    /// Maximum number of assets that are added to an album with a single request
+    album_batch_size: usize,
+
+    /// Number of assets above which a sync has to be confirmed, removals always have to be
+    /// confirmed. None if nothing has to be confirmed.
+    confirm_above: Option<usize>,
+
+    /// Upload all assets of the source album instead of only the missing ones
+    force: bool,
+
+    /// Maximum number of assets that are synced per pair of albums
+    limit: Option<usize>,
+
+    /// Order in which the missing assets are synced
+    order: SyncOrder,
+
+    /// List the assets that are skipped because they already are in the target album
+    report_skipped: bool,
+
+    /// Receives the progress of the downloads and uploads
+    progress: Arc<dyn Progress>,
+
+    /// Files downloaded during the current run, so they are not downloaded again for another
+    /// album
+    downloads: Downloads,
+
+    /// Assets added during the current run, recorded in the --state file after every batch
+    state: State,
+}
+
+impl TryFrom<&Args> for SyncOptions {
+    type Error = anyhow::Error;
+
+    fn try_from(args: &Args) -> Result<Self> {
+        Ok(Self {
+            dry_run: args.dry_run,
+            retries: args.retries,
+            verify: !args.no_verify,
+            verify_upload: args.verify_upload,
+            pipe: args.pipe,
+            resumable_min_size: args.resumable.then_some(RESUMABLE_MIN_SIZE),
+            checksum_algorithm: args.checksum_algorithm,
+            match_by: args.match_by,
+            skip_existing_by_name: args.skip_existing_by_name,
+            quality: args.quality,
+            download_concurrency: args.download_concurrency,
+            upload_concurrency: args.upload_concurrency,
+            bulk_check: !args.no_bulk_check && !args.force,
+            // The skipped assets can only be reported from the full listing
+            quick_check: !args.no_quick_check && !args.report_skipped && !args.force,
+            continue_on_error: args.continue_on_error,
+            filter: Filter {
+                since: args.since,
+                until: args.until,
+                only: args.only,
+                skip: args.skip.clone(),
+                include: filter::glob_set(&args.include)?,
+                exclude: filter::glob_set(&args.exclude)?,
+                max_size: args.max_file_size,
+                min_size: args.min_file_size,
+                include_archived: args.include_archived,
+            },
+            output: args.output,
+            quiet: args.quiet,
+            mirror: args.mirror,
+            sync_album_metadata: args.sync_album_metadata,
+            add_to_album: !args.no_add_to_album,
+            confirm_above: (!args.yes).then_some(args.confirm_above),
+            album_batch_size: args.album_batch_size,
+            force: args.force,
+            limit: args.limit,
+            order: args.order,
+            report_skipped: args.report_skipped,
+            progress: Arc::new(progress::Terminal),
+            downloads: Downloads::default(),
+            state: args
+                .state
+                .as_deref()
+                .map(State::load)
+                .transpose()?
+                .unwrap_or_default(),
+        })
+    }
+}
+
+/// How requests to an immich instance are authenticated
+#[derive(Debug, Clone)]
+enum Auth {
+    /// Access key of a shared link, sent as query parameter
+    SharedLink(String),
+
+    /// API key of a user, sent as header
+    ApiKey(String),
 }
 
 /// A shared link which can be used to download an upload assets
-#[derive(Deserialize, Debug)]
+#[derive(Debug)]
 struct SharedLink {
     album: Album,
 
-    /// Access key, parsed from a share link
-    key: String,
+    /// Credentials used for all requests
+    auth: Auth,
 
     /// Base url of an immich instance, parsed from a share link
-    #[serde(skip)]
     base_url: String,
+
+    /// Path of the API below the base url, e.g. "/api"
+    api_path: String,
+
+    /// Number of parallel requests when downloading from or uploading to this link
+    concurrency: usize,
+
+    /// Name of the peer in the config
+    peer: String,
+
+    /// Whether assets can be uploaded to the album
+    allow_upload: bool,
+
+    /// Whether the link shares a list of assets instead of an album
+    asset_share: bool,
+
+    /// Whether the instance supports resumable uploads, checked on the first large upload
+    resumable: tokio::sync::OnceCell<bool>,
+}
+
+/// Struct to deserialize the response of the shared link endpoint
+#[derive(Deserialize, Debug)]
+struct SharedLinkResponse {
+    /// Id of the share link
+    id: Option<String>,
+
+    /// The shared album, not set for links that share a list of assets
+    album: Option<Album>,
+
+    /// The shared assets of links that don't share an album
+    #[serde(default)]
+    assets: Vec<Asset>,
+
+    description: Option<String>,
+
+    /// Access key, parsed from a share link
+    key: String,
+
+    /// Whether the link allows uploads, assumed if not reported by the server
+    #[serde(alias = "allowUpload", default = "allow_upload_default")]
+    allow_upload: bool,
+}
+
+fn allow_upload_default() -> bool {
+    true
 }
 
 /// An shared album that holds a list of its assets
@@ -55,8 +615,22 @@ struct Album {
     name: String,
     id: String,
 
+    /// Time of the last modification of the album
+    #[serde(alias = "updatedAt")]
+    updated_at: Option<DateTime<Utc>>,
+
+    /// Number of assets in the album as reported by immich
+    #[serde(alias = "assetCount")]
+    asset_count: Option<usize>,
+
+    description: Option<String>,
+
     #[serde(skip)]
     assets: Vec<Asset>,
+
+    /// Whether the assets have been fetched
+    #[serde(skip)]
+    listed: bool,
 }
 
 /// An asses (e.g. image or video)
@@ -72,231 +646,4320 @@ struct Asset {
     #[serde(alias = "originalFileName")]
     file_name: String,
 
-    /// Will be parsed from a json response
-    #[serde(alias = "deviceAssetId")]
-    device_asset_id: String,
+    /// Will be parsed from a json response, missing e.g. for assets uploaded via the web
+    #[serde(alias = "deviceAssetId", default)]
+    device_asset_id: Option<String>,
 
-    /// Will be parsed from a json response
-    #[serde(alias = "deviceId")]
-    device_id: String,
+    /// Will be parsed from a json response, not included in every response
+    #[serde(alias = "deviceId", default)]
+    device_id: Option<String>,
 
-    /// Will be parsed from a json response
-    #[serde(alias = "fileCreatedAt")]
-    file_created_at: String,
+    /// Will be parsed from a json response, not included in every response
+    #[serde(alias = "fileCreatedAt", default)]
+    file_created_at: Option<String>,
+
+    /// Will be parsed from a json response, not included in every response
+    #[serde(alias = "fileModifiedAt", default)]
+    file_modified_at: Option<String>,
 
     /// Will be parsed from a json response
-    #[serde(alias = "fileModifiedAt")]
-    file_modified_at: String,
+    #[serde(rename = "type", default)]
+    asset_type: AssetType,
+
+    /// Will be parsed from a json response, not included in every response
+    #[serde(alias = "exifInfo", default)]
+    exif_info: Option<ExifInfo>,
+
+    /// Will be parsed from a json response, not included in every response
+    #[serde(alias = "isArchived", default)]
+    is_archived: bool,
+
+    /// Will be parsed from a json response, not included in every response
+    #[serde(alias = "isTrashed", default)]
+    is_trashed: bool,
+
+    /// Will be parsed from a json response, "archive" for archived assets on newer servers
+    /// that replaced `isArchived`
+    #[serde(default)]
+    visibility: Option<String>,
+
+    /// Will be parsed from a json response, the id of the video of a live photo
+    #[serde(alias = "livePhotoVideoId", default)]
+    live_photo_video_id: Option<String>,
 
     /// The location of this asset after it has been downloaded
     path: Option<PathBuf>,
+
+    /// The downloaded video of a live photo
+    #[serde(skip)]
+    live_video: Option<Box<Asset>>,
+}
+
+/// Metadata of an asset as reported by immich
+#[derive(Deserialize, Debug, Default, Eq, PartialEq, Hash, Clone)]
+struct ExifInfo {
+    /// Size of the original file
+    #[serde(alias = "fileSizeInByte")]
+    file_size: Option<u64>,
+}
+
+/// What makes two assets in different albums the same asset
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum MatchBy {
+    /// Same content
+    #[default]
+    Checksum,
+
+    /// Same file name
+    Filename,
+
+    /// Same file name and creation time
+    #[value(name = "filename+date")]
+    FilenameDate,
+}
+
+impl MatchBy {
+    /// The identity of the asset
+    fn key(self, asset: &Asset) -> String {
+        match self {
+            MatchBy::Checksum => asset.checksum.clone(),
+            MatchBy::Filename => asset.file_name.clone(),
+            MatchBy::FilenameDate => format!(
+                "{}\0{}",
+                asset.file_name,
+                asset.file_created_at.as_deref().unwrap_or_default()
+            ),
+        }
+    }
+}
+
+/// Order in which the missing assets are synced
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum SyncOrder {
+    /// Oldest assets first
+    #[default]
+    Oldest,
+
+    /// Newest assets first
+    Newest,
+}
+
+impl SyncOrder {
+    /// Sort the assets by their creation time in this order. Assets without a valid creation
+    /// time are placed last, in the order of the album.
+    fn sort(self, assets: &mut [Asset]) {
+        assets.sort_by_cached_key(|asset| {
+            let created = asset.file_created_at.as_deref();
+            let created = created.and_then(|c| DateTime::parse_from_rfc3339(c).ok());
+            let key = created.map(|created| match self {
+                SyncOrder::Oldest => created.timestamp_micros(),
+                SyncOrder::Newest => -created.timestamp_micros(),
+            });
+            (key.is_none(), key)
+        });
+    }
+}
+
+/// Version of an asset that is downloaded
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Quality {
+    /// The original file
+    #[default]
+    Original,
+
+    /// Web-optimized version, the one shown when opening an asset in immich
+    Preview,
+
+    /// Small version, the one shown in the timeline
+    Thumbnail,
+}
+
+impl Quality {
+    /// Path and query of the download of this version of the asset. Videos are downloaded as
+    /// the version immich plays back, which is transcoded depending on the settings of the
+    /// server, since their thumbnail is a still image.
+    fn download_path(self, asset: &Asset) -> (String, [(&'static str, &'static str); 1]) {
+        let id = &asset.id;
+        match self {
+            Quality::Original => (format!("assets/{id}/original"), [("edited", "true")]),
+            _ if asset.asset_type == AssetType::Video => {
+                (format!("assets/{id}/video/playback"), [("edited", "true")])
+            }
+            Quality::Preview => (format!("assets/{id}/thumbnail"), [("size", "preview")]),
+            Quality::Thumbnail => (format!("assets/{id}/thumbnail"), [("size", "thumbnail")]),
+        }
+    }
+}
+
+/// Type of an asset as reported by immich
+#[derive(Deserialize, Debug, Default, Eq, PartialEq, Hash, Clone, Copy)]
+#[serde(rename_all = "UPPERCASE")]
+enum AssetType {
+    Image,
+    Video,
+    Audio,
+    #[default]
+    #[serde(other)]
+    Other,
 }
 
 /// Struct to deserialize responses containing assets
 #[derive(Deserialize, Debug)]
 struct AssetResponse {
     assets: Vec<Asset>,
+
+    #[serde(alias = "updatedAt")]
+    updated_at: Option<DateTime<Utc>>,
+
+    #[serde(alias = "assetCount")]
+    asset_count: Option<usize>,
 }
 
 /// Struct to serialize responses from uploading assets
 #[derive(Deserialize, Debug)]
 struct UploadResponse {
     id: String,
-}
 
-impl SharedLink {
-    /// Create a SharedLink by parsing the given link
-    async fn new(shared_link: &str, client: &Client) -> Result<Self> {
-        let mut s = shared_link.split("/share/");
-        let base_url = s.next().context("Invalid share link")?;
-        let key = s.next().context("Invalid share link")?;
-        let url = format!("{base_url}/api/shared-links/me?key={key}");
-        let res = client.get(url).send().await?;
+    /// "created" for a new asset or "duplicate" if the user already has an asset with the same
+    /// checksum, whose id is returned then
+    #[serde(default)]
+    status: Option<String>,
+
+    /// Set by older servers instead of `status`
+    #[serde(default)]
+    duplicate: bool,
+}
 
-        let mut shared_link = res.json::<SharedLink>().await?;
-        shared_link.base_url = base_url.to_owned();
-        Ok(shared_link)
+impl UploadResponse {
+    /// Whether the asset already existed on the server, e.g. because an earlier attempt of a
+    /// retried upload succeeded although its response was lost
+    fn is_duplicate(&self) -> bool {
+        self.duplicate || self.status.as_deref() == Some("duplicate")
     }
+}
 
-    /// Fill the list of asset that are currently contained in the shared album
-    async fn get_assets(&mut self, client: &Client) -> Result<()> {
-        let url = format!(
-            "{}/api/albums/{}?key={}",
-            &self.base_url, &self.album.id, &self.key
-        );
-        let res = client.get(&url).send().await?;
+/// Result of adding a single asset to an album
+#[derive(Deserialize, Debug)]
+struct AddToAlbumResult {
+    id: String,
+    success: bool,
 
-        let asset_res = res.json::<AssetResponse>().await?;
-        self.album.assets = asset_res.assets;
+    /// Reason why the asset was not added, e.g. "no_permission" or "duplicate"
+    error: Option<String>,
+}
 
-        Ok(())
-    }
+/// Struct to deserialize responses from the bulk upload check
+#[derive(Deserialize, Debug)]
+struct BulkUploadCheckResponse {
+    results: Vec<BulkUploadCheckResult>,
+}
 
-    /// Download the given list of assets. The dowload path will be stored in the assets.
-    async fn download_assets(
-        &self,
-        assets: &mut [Asset],
-        client: &Client,
-        dir: &Path,
-    ) -> Result<()> {
-        let mut download_stream = stream::iter(assets.iter_mut().map(|asset| {
-            let url = format!(
-                "{}/api/assets/{}/original?key={}&edited=true",
-                self.base_url, asset.id, self.key
-            );
-            let asset_file_name = asset.file_name.clone();
-            let dir = dir.to_path_buf();
-            async move {
-                let res = client.get(&url).send().await?;
-                if !res.status().is_success() {
-                    bail!("Download failed for {}: {}", asset_file_name, res.status());
-                }
-                let bytes = res.bytes().await?;
+/// Result of the bulk upload check for a single asset
+#[derive(Deserialize, Debug)]
+struct BulkUploadCheckResult {
+    /// Id of the checked asset as given in the request
+    id: String,
 
-                let dest_path = dir.join(&asset_file_name);
-                asset.path = Some(dest_path.clone());
-                let mut dest_file = File::create(&dest_path)?;
-                dest_file.write_all(&bytes)?;
+    /// Id of the asset on the server, set if the asset already exists
+    #[serde(alias = "assetId")]
+    asset_id: Option<String>,
+}
 
-                Ok(())
+impl SharedLink {
+    /// Create a SharedLink for the given album of the peer, either from its share link or its
+    /// API key
+    async fn from_source(
+        source: &AlbumSource<'_>,
+        peer: &str,
+        concurrency: usize,
+        client: &Client,
+    ) -> Result<Self> {
+        match source {
+            AlbumSource::SharedLink {
+                link,
+                api_base_url,
+                api_path,
+            } => Self::new(link, *api_base_url, *api_path, peer, concurrency, client).await,
+            AlbumSource::ApiKey {
+                base_url,
+                api_key,
+                album_id,
+                api_path,
+            } => {
+                Self::with_api_key(
+                    base_url,
+                    api_key,
+                    album_id,
+                    *api_path,
+                    peer,
+                    concurrency,
+                    client,
+                )
+                .await
             }
-        }))
-        .buffer_unordered(4);
-        while let Some(result) = download_stream.next().await {
-            result?;
         }
-
-        Ok(())
     }
 
-    /// Upload the given list of assets. The assets will be added to the album afterwards
-    async fn upload_assets(&self, client: &Client, assets: &[Asset]) -> Result<()> {
-        let upload_stream = stream::iter(assets.iter().map(|original_asset| {
-            let url = format!("{}/api/assets?key={}", self.base_url, self.key);
-            async move {
-                let form = reqwest::multipart::Form::new()
-                    .text("deviceId", original_asset.device_id.clone())
-                    .text("deviceAssetId", original_asset.device_asset_id.clone())
-                    .text("fileCreatedAt", original_asset.file_created_at.clone())
-                    .text("fileModifiedAt", original_asset.file_modified_at.clone())
-                    .file(
-                        "assetData",
-                        original_asset
-                            .path
-                            .clone()
-                            .context("Asset not downloaded")?,
-                    )
-                    .await?;
-
-                let res = client.post(url).multipart(form).send().await?;
-                if !res.status().is_success() {
-                    bail!(
-                        "Upload failed with status {}: {}",
-                        res.status(),
-                        res.text().await?
-                    );
-                }
-                let response = res.json::<UploadResponse>().await?;
-
-                Ok(response)
+    /// Create a SharedLink by parsing the given link of the peer. API requests go to
+    /// `api_base_url` if given and to the instance of the link otherwise, below `api_path`.
+    /// Fails with [`IssError::DeadLink`] if the server does not accept the link anymore.
+    async fn new(
+        shared_link: &str,
+        api_base_url: Option<&str>,
+        api_path: Option<&str>,
+        peer: &str,
+        concurrency: usize,
+        client: &Client,
+    ) -> Result<Self> {
+        let (base_url, key) =
+            config::parse_share_link(shared_link).map_err(|e| IssError::InvalidShareLink {
+                peer: peer.to_owned(),
+                source: e.into(),
+            })?;
+        let base_url = match api_base_url {
+            Some(api_base_url) => api_base_url.trim_end_matches('/').to_owned(),
+            None => base_url,
+        };
+        let api_path = normalize_api_path(api_path);
+        let url = api_url(&base_url, &api_path, "shared-links/me");
+        let res = ratelimit::send(client.get(url).query(&[("key", &key)])).await?;
+        let status = res.status();
+        if matches!(
+            status,
+            StatusCode::UNAUTHORIZED
+                | StatusCode::FORBIDDEN
+                | StatusCode::NOT_FOUND
+                | StatusCode::GONE
+        ) {
+            debug!(%status, body = res.text().await?, "Share link rejected");
+            return Err(IssError::DeadLink {
+                peer: peer.to_owned(),
             }
-        }))
-        .buffer_unordered(4);
+            .into());
+        }
+        if !status.is_success() {
+            bail!("Fetching share link of peer '{peer}' failed: {status}");
+        }
 
-        let ids: Vec<String> = upload_stream
-            .map(|response| response.map(|r| r.id))
-            .try_collect()
-            .await?;
+        let res = res.json::<SharedLinkResponse>().await?;
+        let (album, asset_share) = match res.album {
+            Some(album) => (album, false),
+            // A link that shares a list of assets is treated like an album that holds them
+            None => {
+                let album = Album {
+                    name: res
+                        .description
+                        .unwrap_or_else(|| "Shared assets".to_owned()),
+                    id: res.id.unwrap_or_else(|| res.key.clone()),
+                    updated_at: None,
+                    asset_count: None,
+                    description: None,
+                    assets: Vec::new(),
+                    listed: false,
+                };
+                (album, true)
+            }
+        };
+        Ok(Self {
+            album,
+            auth: Auth::SharedLink(res.key),
+            base_url,
+            api_path,
+            concurrency,
+            peer: peer.to_owned(),
+            // Assets can only be added to albums
+            allow_upload: res.allow_upload && !asset_share,
+            asset_share,
+            resumable: Default::default(),
+        })
+    }
 
-        let url = format!(
-            "{}/api/albums/{}/assets?key={}",
-            self.base_url, self.album.id, self.key
-        );
-        let mut map = HashMap::new();
-        map.insert("ids", ids);
-        let res = client.put(url).json(&map).send().await?;
+    /// Create a SharedLink that accesses an album of the peer with the API key of a user
+    async fn with_api_key(
+        base_url: &str,
+        api_key: &str,
+        album_id: &str,
+        api_path: Option<&str>,
+        peer: &str,
+        concurrency: usize,
+        client: &Client,
+    ) -> Result<Self> {
+        let mut link = Self {
+            album: Album {
+                name: String::new(),
+                id: album_id.to_owned(),
+                updated_at: None,
+                asset_count: None,
+                description: None,
+                assets: Vec::new(),
+                listed: false,
+            },
+            auth: Auth::ApiKey(api_key.to_owned()),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            api_path: normalize_api_path(api_path),
+            concurrency,
+            peer: peer.to_owned(),
+            allow_upload: true,
+            asset_share: false,
+            resumable: Default::default(),
+        };
+        let req = link
+            .request(client, Method::GET, &format!("albums/{album_id}"))
+            .query(&[("withoutAssets", "true")]);
+        let res = ratelimit::send(req).await?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(IssError::AlbumNotFound {
+                album_id: album_id.to_owned(),
+                peer: peer.to_owned(),
+            }
+            .into());
+        }
         if !res.status().is_success() {
             bail!(
-                "Adding to album {} failed: {}",
-                self.album.name,
-                res.text().await?
+                "Fetching album {album_id} of peer '{peer}' failed: {}",
+                res.status()
             );
         }
+        link.album = res.json::<Album>().await?;
 
-        Ok(())
+        Ok(link)
     }
 
-    /// Upload all assets that are contained in the other SharedLink to this SharedLink.
-    async fn upload_missing(
-        &mut self,
-        other: &Self,
-        dry_run: bool,
-        client: &Client,
-        dir: &Path,
-    ) -> Result<()> {
-        self.get_assets(client).await?;
-        let mut missing = other.album.missing_from_other(&self.album);
-        if missing.is_empty() {
-            println!("No assets to synchronize");
-        } else if dry_run {
-            println!("Assets that would be synced:");
-            for asset in &missing {
-                println!("{}", asset.file_name);
-            }
-            return Ok(());
-        } else {
-            println!("Uploading {} missing assets", missing.len());
-            other.download_assets(&mut missing, client, dir).await?;
-            self.upload_assets(client, &missing).await?;
+    /// Send a request with `send`. If a share link is rejected with 401, it is checked whether
+    /// the server still accepts the link and the request is retried once if it does.
+    async fn retry_unauthorized<F, Fut>(&self, client: &Client, send: F) -> Result<Response>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Response>>,
+    {
+        let res = send().await?;
+        let Auth::SharedLink(key) = &self.auth else {
+            return Ok(res);
+        };
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
         }
+        warn!(
+            "The share link of {} was rejected with 401, retrying once",
+            self.describe()
+        );
+        let req = client
+            .get(self.api_url("shared-links/me"))
+            .query(&[("key", key)]);
+        let status = ratelimit::send(req).await?.status();
+        if !status.is_success() {
+            let error = anyhow::Error::from(IssError::DeadLink {
+                peer: self.peer.clone(),
+            });
+            return Err(error.context(format!(
+                "The share link of {} was rejected with 401 and is not accepted anymore: {status}",
+                self.describe()
+            )));
+        }
+        send().await
+    }
 
-        Ok(())
+    /// Whether both links access the API of the same immich instance
+    fn same_instance(&self, other: &Self) -> bool {
+        self.base_url == other.base_url && self.api_path == other.api_path
     }
-}
 
-impl Album {
-    /// Get all assets that are in the other Album but not in this album
-    fn missing_from_other(&self, other: &Self) -> Vec<Asset> {
-        let other_checksums: HashSet<_> = other.assets.iter().map(|a| &a.checksum).collect();
-        let missing_ids: Vec<Asset> = self
-            .assets
-            .iter()
-            .filter(|asset| !other_checksums.contains(&asset.checksum))
-            .cloned()
-            .collect();
-        missing_ids
+    /// Name and id of the album together with the name of its peer, for messages
+    fn describe(&self) -> String {
+        format!(
+            "album '{}' ({}) of peer '{}'",
+            self.album.name, self.album.id, self.peer
+        )
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
-    let raw_config = fs::read_to_string(args.config)?;
-    let config: Config = toml::from_str(&raw_config)?;
+    /// URL of the given path of the API
+    fn api_url(&self, path: &str) -> String {
+        api_url(&self.base_url, &self.api_path, path)
+    }
 
-    let client = reqwest::Client::new();
+    /// Build an authenticated request to the given path of the API
+    fn request(&self, client: &Client, method: Method, path: &str) -> RequestBuilder {
+        self.request_url(client, method, &self.api_url(path))
+    }
 
-    for (name, peer) in &config {
-        let mut this = SharedLink::new(&peer.shared_link, &client).await?;
-        this.get_assets(&client).await?;
+    /// Build an authenticated request to the given URL of the instance
+    fn request_url(&self, client: &Client, method: Method, url: &str) -> RequestBuilder {
+        let builder = client.request(method, url);
+        match &self.auth {
+            Auth::SharedLink(key) => builder.query(&[("key", key)]),
+            Auth::ApiKey(key) => builder.header("x-api-key", key),
+        }
+    }
+
+    /// Fill the list of asset that are currently contained in the shared album
+    async fn get_assets(&mut self, client: &Client) -> Result<()> {
+        if self.asset_share {
+            let req = self.request(client, Method::GET, "shared-links/me");
+            let res = ratelimit::send(req).await?;
+            if !res.status().is_success() {
+                bail!(
+                    "Fetching assets of {} failed: {}",
+                    self.describe(),
+                    res.status()
+                );
+            }
+            self.album.assets = res.json::<SharedLinkResponse>().await?.assets;
+            self.album.listed = true;
+            return Ok(());
+        }
+        let mut assets = Vec::new();
+        let mut ids = HashSet::new();
+        for page in 1.. {
+            let req = self
+                .request(client, Method::GET, &format!("albums/{}", self.album.id))
+                .query(&[("page", page), ("size", PAGE_SIZE)]);
+            let res = ratelimit::send(req).await?;
+            if !res.status().is_success() {
+                bail!(
+                    "Fetching assets of {} failed: {}",
+                    self.describe(),
+                    res.status()
+                );
+            }
+
+            let asset_res = res.json::<AssetResponse>().await?;
+            self.album.updated_at = asset_res.updated_at;
+            self.album.asset_count = asset_res.asset_count;
+            let count = asset_res.assets.len();
+            let known = assets.len();
+            assets.extend(
+                asset_res
+                    .assets
+                    .into_iter()
+                    .filter(|asset| ids.insert(asset.id.clone())),
+            );
+
+            // Servers without pagination return the whole album for every page, so stop at the
+            // first page that is not full or does not contain any new assets
+            if count < PAGE_SIZE || assets.len() == known {
+                break;
+            }
+        }
+        self.album.assets = assets;
+        self.album.listed = true;
+
+        Ok(())
+    }
+
+    /// Fetch the assets of the album, unless they have already been fetched
+    async fn ensure_listed(&mut self, client: &Client) -> Result<()> {
+        if !self.album.listed {
+            self.get_assets(client).await?;
+        }
+        Ok(())
+    }
+
+    /// Download a single asset into the given directory and store the path in the asset. The
+    /// video of a live photo is downloaded as well, if that fails only the still image is synced.
+    /// Returns the size of the downloaded files and whether the file of the asset was reused from
+    /// an earlier download of this run.
+    #[instrument(name = "download", skip_all, fields(id = %asset.id, file = %asset.file_name))]
+    async fn download_asset(
+        &self,
+        asset: &mut Asset,
+        client: &Client,
+        dir: &Path,
+        options: &SyncOptions,
+        progress: &dyn BatchProgress,
+    ) -> Result<(u64, bool)> {
+        let (path, size) = self
+            .download_file(asset, client, dir, options, progress)
+            .await?;
+        asset.path = Some(path);
+        let reused = size.is_none();
+        let mut size = size.unwrap_or_default();
+        if let Some(video_id) = &asset.live_photo_video_id {
+            match self
+                .download_live_video(video_id, client, dir, options, progress)
+                .await
+            {
+                Ok((video, video_size)) => {
+                    asset.live_video = Some(Box::new(video));
+                    size += video_size;
+                }
+                Err(e) => warn!(
+                    "Video of live photo {} could not be downloaded, syncing only the still \
+                     image: {e:#}",
+                    asset.file_name
+                ),
+            }
+        }
+
+        Ok((size, reused))
+    }
 
-        for other_name in &peer.sync_with {
-            let other = &config[other_name];
-            let mut other = SharedLink::new(&other.shared_link, &client).await?;
-            other.get_assets(&client).await?;
+    /// Fetch the metadata of the video of a live photo and download it into the given directory
+    async fn download_live_video(
+        &self,
+        video_id: &str,
+        client: &Client,
+        dir: &Path,
+        options: &SyncOptions,
+        progress: &dyn BatchProgress,
+    ) -> Result<(Asset, u64)> {
+        let mut video = self.live_video(video_id, client, options).await?;
+        let (path, size) = self
+            .download_file(&video, client, dir, options, progress)
+            .await?;
+        video.path = Some(path);
+        Ok((video, size.unwrap_or_default()))
+    }
 
-            println!(
-                "Adding assets from {} ({}) to {} ({}) ...",
-                other_name, other.album.name, name, this.album.name,
+    /// Fetch the metadata of the video of a live photo
+    async fn live_video(
+        &self,
+        video_id: &str,
+        client: &Client,
+        options: &SyncOptions,
+    ) -> Result<Asset> {
+        let path = format!("assets/{video_id}");
+        let res = retry::with_retry(options.retries, "Fetching live photo video", || async {
+            Ok(ratelimit::send(self.request(client, Method::GET, &path)).await?)
+        })
+        .await?;
+        if !res.status().is_success() {
+            bail!(
+                "Fetching video {video_id} from {} failed: {}",
+                self.describe(),
+                res.status()
             );
+        }
+        Ok(res.json().await?)
+    }
+
+    /// Download the file of an asset into the given directory, unless a file with the same
+    /// checksum has already been downloaded during this run. That file is linked into the
+    /// directory instead, so every album's directory contains its files. Returns the path and the
+    /// number of downloaded bytes, None if the file was reused.
+    async fn download_file(
+        &self,
+        asset: &Asset,
+        client: &Client,
+        dir: &Path,
+        options: &SyncOptions,
+        progress: &dyn BatchProgress,
+    ) -> Result<(PathBuf, Option<u64>)> {
+        let mut size = 0;
+        let (path, downloaded) = options
+            .downloads
+            .get_or_download(&asset.checksum, || async {
+                let (path, downloaded) = self
+                    .fetch_file(asset, client, dir, options, progress)
+                    .await?;
+                size = downloaded;
+                Ok(path)
+            })
+            .await?;
+        if downloaded {
+            return Ok((path, Some(size)));
+        }
+        debug!(path = %path.display(), "Reusing earlier download");
+        if path.parent() == Some(dir) {
+            return Ok((path, None));
+        }
+        Ok((link_download(&path, dir, asset)?, None))
+    }
+
+    /// Download the file of an asset into the given directory. Returns the path and the size of
+    /// the file.
+    async fn fetch_file(
+        &self,
+        asset: &Asset,
+        client: &Client,
+        dir: &Path,
+        options: &SyncOptions,
+        progress: &dyn BatchProgress,
+    ) -> Result<(PathBuf, u64)> {
+        let res = self.start_download(asset, client, options).await?;
+        let (mut dest_file, download) = create_download_file(dir)?;
+        // Only the original has the checksum of the asset
+        let verify = options.verify && options.quality == Quality::Original;
+        let mut hasher = verify.then(|| options.checksum_algorithm.hasher());
+        let mut size = 0;
+        let mut body = res.bytes_stream();
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|e| retry::describe_timeout(e.into()))?;
+            if let Some(hasher) = &mut hasher {
+                hasher.update(&chunk);
+            }
+            let written = dest_file.write_all(&chunk).await;
+            written.map_err(|e| storage_error(e, dir, asset))?;
+            size += chunk.len() as u64;
+            progress.bytes(chunk.len() as u64);
+        }
+        let flushed = dest_file.flush().await;
+        flushed.map_err(|e| storage_error(e, dir, asset))?;
+
+        if let Some(hasher) = hasher {
+            let checksum = hasher.finish();
+            if checksum != asset.checksum {
+                return Err(IssError::ChecksumMismatch {
+                    asset: asset.file_name.clone(),
+                    album: self.describe(),
+                    expected: asset.checksum.clone(),
+                    actual: checksum,
+                }
+                .into());
+            }
+        }
+        drop(dest_file);
+        let dest_path = persist_download(dir, asset, download)?;
+        if let Some(modified) = &asset.file_modified_at {
+            set_file_time(&dest_path, modified);
+        }
+        debug!(size, path = %dest_path.display(), "Download finished");
+
+        Ok((dest_path, size))
+    }
 
-            let tmp_dir = tempfile::Builder::new().prefix("iss").tempdir()?;
-            let path = tmp_dir.path();
+    /// Start the download of the file of an asset in the synced quality, without reading the
+    /// body yet
+    async fn start_download(
+        &self,
+        asset: &Asset,
+        client: &Client,
+        options: &SyncOptions,
+    ) -> Result<Response> {
+        let (path, query) = options.quality.download_path(asset);
+        let what = format!("Download of {}", asset.file_name);
+        let send = || {
+            retry::with_retry(options.retries, &what, || async {
+                let req = self.request(client, Method::GET, &path);
+                Ok(ratelimit::send(req.query(&query)).await?)
+            })
+        };
+        let res = self.retry_unauthorized(client, send).await?;
+        debug!(status = %res.status(), "Download started");
+        if !res.status().is_success() {
+            return Err(IssError::DownloadFailed {
+                asset: asset.file_name.clone(),
+                album: self.describe(),
+                status: res.status(),
+            }
+            .into());
+        }
+        // An expired link may lead to a login page instead of the file
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok());
+        if let Some(content_type) = content_type
+            && !is_media_type(content_type)
+        {
+            return Err(IssError::NotAFile {
+                asset: asset.file_name.clone(),
+                album: self.describe(),
+                content_type: content_type.to_owned(),
+            }
+            .into());
+        }
+        Ok(res)
+    }
+
+    /// Start downloading the file of an asset as part of an upload form, for `--pipe`. The
+    /// checksum can't be verified before the upload completes, so this is only used with
+    /// `--no-verify`. The streamed bytes are counted in `streamed`.
+    async fn streamed_part(
+        &self,
+        asset: &Asset,
+        client: &Client,
+        options: &SyncOptions,
+        streamed: &Arc<AtomicU64>,
+    ) -> Result<Part> {
+        let res = self.start_download(asset, client, options).await?;
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_owned);
+        streamed.store(0, Ordering::Relaxed);
+        let streamed = Arc::clone(streamed);
+        let body = res.bytes_stream().map(move |chunk| {
+            let chunk = chunk.map_err(|e| retry::describe_timeout(e.into()))?;
+            streamed.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            Ok::<_, anyhow::Error>(chunk)
+        });
+        let part = Part::stream(Body::wrap_stream(body));
+        match content_type {
+            Some(content_type) => Ok(part.mime_str(&content_type)?),
+            None => Ok(part),
+        }
+    }
+
+    /// Download the given list of assets. The dowload path will be stored in the assets. The
+    /// downloads are counted in the summary, which also collects the assets that failed to
+    /// download if `continue_on_error` is set.
+    async fn download_assets(
+        &self,
+        assets: &mut [Asset],
+        client: &Client,
+        dir: &Path,
+        options: &SyncOptions,
+        summary: &mut PairSummary,
+    ) -> Result<()> {
+        let batch = options.progress.batch(Transfer::Download, assets.len());
+        let progress = &*batch;
+        let mut download_stream = stream::iter(assets.iter_mut().map(|asset| async move {
+            let start = Instant::now();
+            let result = self
+                .download_asset(asset, client, dir, options, progress)
+                .await;
+            match &result {
+                Ok((size, _)) => progress.done(asset, *size, start.elapsed()),
+                Err(e) => progress.failed(asset, e),
+            }
+            result.map_err(|e| (asset.clone(), e))
+        }))
+        .buffer_unordered(options.download_concurrency.unwrap_or(self.concurrency));
+
+        while let Some(result) = download_stream.next().await {
+            match result {
+                Ok((size, reused)) => {
+                    if reused {
+                        summary.reused += 1;
+                    } else {
+                        summary.downloaded += 1;
+                    }
+                    summary.bytes += size;
+                }
+                Err((_, error)) if !options.continue_on_error => return Err(error),
+                Err((asset, error)) => {
+                    warn!("{error:#}");
+                    summary.fail((asset, error));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ask the server which of the given assets already exist in the library. Returns the ids of
+    /// the existing assets on the server keyed by the ids of the given assets.
+    async fn existing_assets(
+        &self,
+        client: &Client,
+        assets: &[Asset],
+    ) -> Result<HashMap<String, String>> {
+        let checks: Vec<_> = assets
+            .iter()
+            .map(|asset| serde_json::json!({"id": asset.id, "checksum": asset.checksum}))
+            .collect();
+        let req = self
+            .request(client, Method::POST, "assets/bulk-upload-check")
+            .json(&serde_json::json!({"assets": checks}));
+        let res = ratelimit::send(req).await?;
+        if !res.status().is_success() {
+            bail!("{}", res.status());
+        }
+        let response = res.json::<BulkUploadCheckResponse>().await?;
+
+        Ok(response
+            .results
+            .into_iter()
+            .filter_map(|result| Some((result.id, result.asset_id?)))
+            .collect())
+    }
+
+    /// Upload a single downloaded asset. The video of a live photo is uploaded first and linked
+    /// to the still image, if that fails only the still image is uploaded.
+    #[instrument(
+        name = "upload",
+        skip_all,
+        fields(id = %original_asset.id, file = %original_asset.file_name)
+    )]
+    async fn upload_asset(
+        &self,
+        original_asset: &Asset,
+        source: Option<&SharedLink>,
+        client: &Client,
+        options: &SyncOptions,
+        progress: &dyn BatchProgress,
+    ) -> Result<(UploadResponse, u64)> {
+        let mut size = 0;
+        let mut video_id = None;
+        // Streamed live photos have not fetched the metadata of their video yet
+        let mut streamed_video = None;
+        if let (Some(source), Some(id), None) = (
+            source,
+            &original_asset.live_photo_video_id,
+            &original_asset.live_video,
+        ) {
+            match source.live_video(id, client, options).await {
+                Ok(video) => streamed_video = Some(video),
+                Err(e) => warn!(
+                    "Video of live photo {} could not be downloaded, syncing only the still \
+                     image: {e:#}",
+                    original_asset.file_name
+                ),
+            }
+        }
+        if let Some(video) = original_asset
+            .live_video
+            .as_deref()
+            .or(streamed_video.as_ref())
+        {
+            match self
+                .upload_file(video, None, source, client, options, progress)
+                .await
+            {
+                Ok((response, video_size)) => {
+                    video_id = Some(response.id);
+                    size += video_size;
+                }
+                Err(e) => warn!(
+                    "Video of live photo {} could not be uploaded, syncing only the still \
+                     image: {e:#}",
+                    original_asset.file_name
+                ),
+            }
+        }
+        let (response, still_size) = self
+            .upload_file(
+                original_asset,
+                video_id.as_deref(),
+                source,
+                client,
+                options,
+                progress,
+            )
+            .await?;
+        size += still_size;
+
+        Ok((response, size))
+    }
+
+    /// Upload the downloaded file of an asset, linked to the already uploaded video with the
+    /// given id if it is the still image of a live photo. Assets that have not been downloaded
+    /// are streamed from `source`. Returns the response and the size of the file.
+    async fn upload_file(
+        &self,
+        original_asset: &Asset,
+        live_photo_video_id: Option<&str>,
+        source: Option<&SharedLink>,
+        client: &Client,
+        options: &SyncOptions,
+        progress: &dyn BatchProgress,
+    ) -> Result<(UploadResponse, u64)> {
+        let what = format!("Upload of {}", original_asset.file_name);
+        let streamed = Arc::new(AtomicU64::new(0));
+        let send = || {
+            retry::with_retry(options.retries, &what, || async {
+                let data = match (&original_asset.path, source) {
+                    (Some(path), _) => Part::file(path).await?,
+                    (None, Some(source)) => {
+                        source
+                            .streamed_part(original_asset, client, options, &streamed)
+                            .await?
+                    }
+                    (None, None) => bail!("Asset not downloaded"),
+                };
+                // The file on disk may have been renamed to avoid collisions, so the original name
+                // is passed explicitly
+                let data = data.file_name(original_asset.file_name.clone());
+                let (created, modified) = original_asset.upload_times();
+                let form = Form::new()
+                    .text("deviceId", original_asset.upload_device_id())
+                    .text("deviceAssetId", original_asset.upload_device_asset_id())
+                    .text("fileCreatedAt", created)
+                    .text("fileModifiedAt", modified)
+                    .part("assetData", data);
+                let form = match live_photo_video_id {
+                    Some(id) => form.text("livePhotoVideoId", id.to_owned()),
+                    None => form,
+                };
 
-            this.upload_missing(&other, args.dry_run, &client, path)
+                let req = self.request(client, Method::POST, "assets");
+                Ok(ratelimit::send(req.multipart(form)).await?)
+            })
+        };
+        let resumable = match (&original_asset.path, options.resumable_min_size) {
+            (Some(path), Some(min_size)) => {
+                fs::metadata(path)?.len() >= min_size && self.supports_resumable(client).await
+            }
+            _ => false,
+        };
+        let response = if resumable {
+            self.upload_resumable(original_asset, live_photo_video_id, client, options)
+                .await?
+        } else {
+            let res = self.retry_unauthorized(client, send).await?;
+            let status = res.status();
+            debug!(%status, "Upload sent");
+            if status.is_success() {
+                res.json::<UploadResponse>().await?
+            } else {
+                let body = res.text().await?;
+                // Some servers reject an asset that already exists with the id of the existing
+                // asset
+                let existing = serde_json::from_str::<UploadResponse>(&body)
+                    .ok()
+                    .filter(|_| status == StatusCode::CONFLICT);
+                let Some(mut existing) = existing else {
+                    return Err(IssError::UploadFailed {
+                        asset: original_asset.file_name.clone(),
+                        album: self.describe(),
+                        status,
+                        body,
+                    }
+                    .into());
+                };
+                existing.duplicate = true;
+                existing
+            }
+        };
+        let size = match &original_asset.path {
+            Some(path) => fs::metadata(path)?.len(),
+            None => streamed.load(Ordering::Relaxed),
+        };
+        progress.bytes(size);
+        if response.is_duplicate() {
+            debug!(id = %response.id, "Asset already exists on the target, using the existing one");
+        } else {
+            debug!(new_id = %response.id, size, "Upload finished");
+        }
+        if options.verify_upload {
+            self.verify_upload(client, original_asset, &response.id)
                 .await?;
         }
+
+        Ok((response, size))
     }
 
-    Ok(())
+    /// Whether the instance accepts resumable uploads. Only checked once, a failed check counts
+    /// as not supported.
+    async fn supports_resumable(&self, client: &Client) -> bool {
+        *self
+            .resumable
+            .get_or_init(|| async {
+                let req = self.request(client, Method::OPTIONS, RESUMABLE_UPLOAD_PATH);
+                let supported = ratelimit::send(req)
+                    .await
+                    .is_ok_and(|res| tus::is_supported(&res));
+                if !supported {
+                    info!(
+                        "{} does not support resumable uploads, uploading in a single request",
+                        self.describe()
+                    );
+                }
+                supported
+            })
+            .await
+    }
+
+    /// Upload the downloaded file of an asset with a resumable upload, so an interrupted upload
+    /// continues where it stopped. The metadata is the same as for a multipart upload. The tus
+    /// protocol completes an upload with an empty response, so unless the server includes the
+    /// created asset, it is looked up by its checksum.
+    async fn upload_resumable(
+        &self,
+        original_asset: &Asset,
+        live_photo_video_id: Option<&str>,
+        client: &Client,
+        options: &SyncOptions,
+    ) -> Result<UploadResponse> {
+        let path = original_asset
+            .path
+            .as_deref()
+            .context("Asset not downloaded")?;
+        let (created, modified) = original_asset.upload_times();
+        let mut metadata = vec![
+            ("filename", original_asset.file_name.clone()),
+            ("deviceId", original_asset.upload_device_id()),
+            ("deviceAssetId", original_asset.upload_device_asset_id()),
+            ("fileCreatedAt", created),
+            ("fileModifiedAt", modified),
+        ];
+        if let Some(id) = live_photo_video_id {
+            metadata.push(("livePhotoVideoId", id.to_owned()));
+        }
+        let request = |method, url: &str| self.request_url(client, method, url);
+        let endpoint = self.api_url(RESUMABLE_UPLOAD_PATH);
+        let completed = tus::upload(request, &endpoint, path, &metadata, options.retries).await?;
+        let body = match completed {
+            Some(res) => res.text().await?,
+            None => String::new(),
+        };
+        if let Ok(response) = serde_json::from_str::<UploadResponse>(&body) {
+            return Ok(response);
+        }
+        let existing = self
+            .existing_assets(client, std::slice::from_ref(original_asset))
+            .await
+            .context("Looking up the asset of the resumable upload")?;
+        let id = existing.get(&original_asset.id).with_context(|| {
+            format!(
+                "The resumable upload of {} completed, but the asset is not on {}",
+                original_asset.file_name,
+                self.describe()
+            )
+        })?;
+        Ok(UploadResponse {
+            id: id.clone(),
+            status: None,
+            duplicate: false,
+        })
+    }
+
+    /// Check that the asset with the given id on this instance has the same checksum as the
+    /// uploaded asset. If the asset can't be fetched, e.g. because the share link doesn't allow
+    /// it, this is only logged.
+    async fn verify_upload(&self, client: &Client, asset: &Asset, id: &str) -> Result<()> {
+        let req = self.request(client, Method::GET, &format!("assets/{id}"));
+        let res = ratelimit::send(req).await?;
+        if !res.status().is_success() {
+            warn!(
+                "Can not verify the upload of {} to {}: {}",
+                asset.file_name,
+                self.describe(),
+                res.status()
+            );
+            return Ok(());
+        }
+        let uploaded: Asset = res.json().await?;
+        if uploaded.checksum != asset.checksum {
+            return Err(IssError::UploadChecksumMismatch {
+                asset: asset.file_name.clone(),
+                album: self.describe(),
+                expected: asset.checksum.clone(),
+                actual: uploaded.checksum,
+            }
+            .into());
+        }
+        debug!("Upload verified");
+        Ok(())
+    }
+
+    /// Upload the given list of assets, except for the ones in `existing`, which maps the ids
+    /// of assets that already exist on the target to their ids there. Assets that have not been
+    /// downloaded are streamed from `source`. All assets will be added to the album afterwards.
+    /// The uploads are counted in the summary, which also collects the assets that failed to
+    /// upload if `continue_on_error` is set.
+    async fn upload_assets(
+        &self,
+        client: &Client,
+        assets: &[Asset],
+        existing: &HashMap<String, String>,
+        source: Option<&SharedLink>,
+        options: &SyncOptions,
+        summary: &mut PairSummary,
+    ) -> Result<()> {
+        // Assets are added to the album in the order of the given list, regardless of the order
+        // in which their uploads finish
+        let mut pending = PendingIds {
+            album: &self.album.name,
+            ids: Vec::new(),
+        };
+        let mut uploads = Vec::new();
+        for (position, asset) in assets.iter().enumerate() {
+            match existing.get(&asset.id) {
+                Some(id) => pending.ids.push((position, id.clone())),
+                None => uploads.push((position, asset)),
+            }
+        }
+
+        let batch = options.progress.batch(Transfer::Upload, uploads.len());
+        let progress = &*batch;
+        let uploads = uploads
+            .into_iter()
+            .map(|(position, original_asset)| async move {
+                let start = Instant::now();
+                let result = self
+                    .upload_asset(original_asset, source, client, options, progress)
+                    .await;
+                match &result {
+                    Ok((_, size)) => progress.done(original_asset, *size, start.elapsed()),
+                    Err(e) => progress.failed(original_asset, e),
+                }
+                result
+                    .map(|(response, size)| (position, response, size))
+                    .map_err(|e| (original_asset.clone(), e))
+            });
+        let concurrency = options.upload_concurrency.unwrap_or(self.concurrency);
+        let mut upload_stream = stream::iter(uploads).buffer_unordered(concurrency);
+
+        while let Some(result) = upload_stream.next().await {
+            match result {
+                Ok((position, response, size)) => {
+                    // Streamed assets are downloaded by their upload
+                    if source.is_some() {
+                        summary.downloaded += 1;
+                        summary.bytes += size;
+                    }
+                    // Without adding them to the album, the assets are done once uploaded
+                    if !options.add_to_album {
+                        let checksum = assets[position].checksum.as_str();
+                        options.state.insert(&self.album.id, [checksum])?;
+                    }
+                    pending.ids.push((position, response.id));
+                }
+                Err((_, error)) if !options.continue_on_error => return Err(error),
+                Err((asset, error)) => {
+                    warn!("{error:#}");
+                    summary.fail((asset, error));
+                }
+            }
+        }
+        drop(upload_stream);
+        drop(batch);
+        if !options.add_to_album {
+            info!(
+                "Uploaded {} assets without adding them to {}",
+                pending.ids.len(),
+                self.describe()
+            );
+            summary.uploaded += pending.ids.len();
+            pending.ids.clear();
+            return Ok(());
+        }
+
+        pending.ids.sort();
+        let total = pending.ids.len();
+        let mut failed = HashMap::new();
+        for attempt in 1..=options.retries {
+            if pending.ids.is_empty() {
+                break;
+            }
+            if attempt > 1 {
+                warn!(
+                    "{} assets could not be added to {}, retrying",
+                    pending.ids.len(),
+                    self.describe()
+                );
+                tokio::time::sleep(retry::backoff(attempt - 1)).await;
+            }
+            let ids: Vec<_> = pending
+                .ids
+                .iter()
+                .map(|(position, id)| (id.as_str(), assets[*position].checksum.as_str()))
+                .collect();
+            failed = self.add_to_album_in_batches(client, &ids, options).await?;
+            let count = pending.ids.len();
+            pending.ids.retain(|(_, id)| failed.contains_key(id));
+            summary.uploaded += count - pending.ids.len();
+        }
+
+        if total > options.album_batch_size {
+            info!(
+                "Added {} of {total} assets to {} in batches of {}",
+                total - pending.ids.len(),
+                self.describe(),
+                options.album_batch_size
+            );
+        }
+
+        // Without continue_on_error, the ids stay pending so they are reported when dropped
+        let mut failures = pending.ids.iter().map(|(position, id)| {
+            let asset = &assets[*position];
+            let error = anyhow!(
+                "Adding {} (uploaded as {id}) to {} failed: {}",
+                asset.file_name,
+                self.describe(),
+                failed[id]
+            );
+            (asset.clone(), error)
+        });
+        if !options.continue_on_error
+            && let Some((_, error)) = failures.next()
+        {
+            return Err(error);
+        }
+        for (asset, error) in failures {
+            warn!("{error:#}");
+            summary.fail((asset, error));
+        }
+        pending.ids.clear();
+
+        Ok(())
+    }
+
+    /// Add the assets with the given ids to the album with one request per
+    /// `options.album_batch_size` ids. The checksums of the added assets, given next to their
+    /// ids, are recorded in the state after every batch. Returns the ids that could not be
+    /// added, together with the reason. If a request fails, all ids of its batch are returned.
+    async fn add_to_album_in_batches(
+        &self,
+        client: &Client,
+        ids: &[(&str, &str)],
+        options: &SyncOptions,
+    ) -> Result<HashMap<String, String>> {
+        let mut failed = HashMap::new();
+        for batch in ids.chunks(options.album_batch_size) {
+            let batch_ids: Vec<_> = batch.iter().map(|(id, _)| *id).collect();
+            match self.add_to_album(client, &batch_ids).await {
+                Ok(batch_failed) => {
+                    let added = batch
+                        .iter()
+                        .filter(|(id, _)| !batch_failed.contains_key(*id))
+                        .map(|(_, checksum)| *checksum);
+                    options.state.insert(&self.album.id, added)?;
+                    failed.extend(batch_failed);
+                }
+                // The other batches may still succeed
+                Err(e) => {
+                    let error = format!("{e:#}");
+                    failed.extend(batch_ids.iter().map(|id| (id.to_string(), error.clone())));
+                }
+            }
+        }
+        Ok(failed)
+    }
+
+    /// Add the assets with the given ids to the album. Returns the ids that could not be added,
+    /// together with the reason reported by immich. Assets that already are in the album count
+    /// as added.
+    async fn add_to_album(&self, client: &Client, ids: &[&str]) -> Result<HashMap<String, String>> {
+        let path = format!("albums/{}/assets", self.album.id);
+        let req = self
+            .request(client, Method::PUT, &path)
+            .json(&serde_json::json!({"ids": ids}));
+        let res = ratelimit::send(req).await?;
+        if !res.status().is_success() {
+            bail!(
+                "Adding {} assets to {} failed with status {}: {}",
+                ids.len(),
+                self.describe(),
+                res.status(),
+                res.text().await?
+            );
+        }
+        let results: Vec<AddToAlbumResult> = res.json().await?;
+        Ok(results
+            .into_iter()
+            .filter(|r| !r.success && r.error.as_deref() != Some("duplicate"))
+            .map(|r| (r.id, r.error.unwrap_or_else(|| "unknown error".to_owned())))
+            .collect())
+    }
+
+    /// Upload all assets that are contained in the other SharedLink to this SharedLink. The
+    /// returned summary contains the assets that failed to sync if `continue_on_error` is set.
+    async fn upload_missing(
+        &mut self,
+        other: &Self,
+        options: &SyncOptions,
+        cache: Option<&Mutex<Cache>>,
+        client: &Client,
+        dir: &Path,
+    ) -> Result<PairSummary> {
+        if !self.allow_upload {
+            return Err(IssError::UploadNotAllowed {
+                album: self.album.name.clone(),
+                peer: self.peer.clone(),
+            }
+            .into());
+        }
+        let mut summary = PairSummary {
+            from_album: other.album.name.clone(),
+            to_album: self.album.name.clone(),
+            considered: other.album.assets.len(),
+            ..Default::default()
+        };
+        if options.report_skipped {
+            self.report_present(other, options, &mut summary);
+        }
+        if tracing::enabled!(Level::DEBUG) && !options.force {
+            self.log_renamed(other, options.match_by);
+        }
+        let mut missing = if options.force {
+            other.album.assets.clone()
+        } else {
+            other
+                .album
+                .missing_from_other(&self.album, options.match_by)
+        };
+        let mut checksums = HashSet::new();
+        let count = missing.len();
+        missing.retain(|asset| checksums.insert(asset.checksum.clone()));
+        if missing.len() < count {
+            info!(
+                "Skipping {} duplicates with the same checksum as another asset of the album",
+                count - missing.len()
+            );
+        }
+        if options.skip_existing_by_name && !options.force {
+            let skipped = self.album.drop_same_name_and_date(&mut missing);
+            if skipped > 0 {
+                info!(
+                    "Skipping {skipped} assets with the same file name and creation time as an \
+                     asset of the album, but a different checksum"
+                );
+            }
+        }
+        for asset in &missing {
+            if options.filter.outside_size_limits(asset) {
+                let size = HumanBytes(asset.size().unwrap_or_default());
+                info!(
+                    "Skipping {} ({size}), outside of the file size limits",
+                    asset.file_name
+                );
+            }
+        }
+        let count = missing.len();
+        missing.retain(|asset| options.filter.matches(asset));
+        if missing.len() < count {
+            debug!(
+                "Skipping {} assets that don't match the filter",
+                count - missing.len()
+            );
+        }
+        if let Some(cache) = cache.filter(|_| !options.force) {
+            let synced = cache.lock().unwrap().synced(&self.album);
+            let count = missing.len();
+            missing.retain(|asset| !synced.contains(&asset.checksum));
+            if missing.len() < count {
+                info!(
+                    "Skipping {} assets that are already synced according to the cache",
+                    count - missing.len()
+                );
+            }
+        }
+        // The listing of the album may not contain assets that were just added by another sync
+        let count = missing.len();
+        options.state.retain_new(&self.album.id, &mut missing);
+        if missing.len() < count {
+            info!(
+                "Skipping {} assets that were already added by another peer or an interrupted run",
+                count - missing.len()
+            );
+        }
+        summary.skipped = summary.considered - missing.len();
+        options.order.sort(&mut missing);
+        if let Some(limit) = options.limit
+            && missing.len() > limit
+        {
+            info!(
+                "Syncing {limit} of {} missing assets, run again to continue",
+                missing.len()
+            );
+            summary.deferred = missing.len() - limit;
+            missing.truncate(limit);
+        }
+        if options.dry_run {
+            summary.would_sync = Some(missing.iter().map(|a| a.file_name.clone()).collect());
+        } else if let Some(limit) = options.confirm_above
+            && missing.len() > limit
+        {
+            let question = format!(
+                "Sync {} assets ({}) from {} to {}",
+                missing.len(),
+                HumanBytes(missing.iter().filter_map(Asset::size).sum()),
+                other.describe(),
+                self.describe()
+            );
+            if !confirm::ask(question).await? {
+                info!(
+                    "Not syncing {} assets, they are left for the next run",
+                    missing.len()
+                );
+                summary.deferred += missing.len();
+                missing.clear();
+            }
+        }
+
+        if missing.is_empty() {
+            info!("No assets to synchronize");
+        } else if options.dry_run {
+            summary.plan = Some(PlannedPair::new(other, self, &missing));
+            let bytes = missing.iter().filter_map(Asset::size).sum();
+            let unknown = missing.iter().filter(|a| a.size().is_none()).count();
+            summary.would_sync_bytes = Some(bytes);
+            if options.output == OutputFormat::Text && !options.quiet {
+                println!("Assets that would be synced:");
+                for asset in &missing {
+                    println!(
+                        "{} -> {}: {}",
+                        other.album.name, self.album.name, asset.file_name
+                    );
+                }
+                let mut total = format!("{} assets, {}", missing.len(), HumanBytes(bytes));
+                if unknown > 0 {
+                    total.push_str(&format!(" (size of {unknown} assets unknown)"));
+                }
+                println!("{total}");
+            }
+        } else {
+            // Assets of the same instance can be added by their id, if the target may access them
+            let mut added_directly = Vec::new();
+            if self.same_instance(other) && options.add_to_album {
+                let ids: Vec<_> = missing
+                    .iter()
+                    .map(|asset| (asset.id.as_str(), asset.checksum.as_str()))
+                    .collect();
+                let failed = self.add_to_album_in_batches(client, &ids, options).await?;
+                let (added, rest): (Vec<_>, Vec<_>) = missing
+                    .into_iter()
+                    .partition(|asset| !failed.contains_key(&asset.id));
+                if !added.is_empty() {
+                    info!(
+                        "Added {} assets of the same instance without downloading them",
+                        added.len()
+                    );
+                }
+                if !rest.is_empty() {
+                    debug!(?failed, "Assets that could not be added directly");
+                }
+                summary.uploaded += added.len();
+                added_directly = added;
+                missing = rest;
+            }
+
+            info!("Uploading {} missing assets", missing.len());
+            let existing = if options.bulk_check && !missing.is_empty() {
+                self.existing_assets(client, &missing)
+                    .await
+                    .unwrap_or_else(|e| {
+                        warn!("Bulk upload check failed ({e:#}), downloading all assets");
+                        HashMap::new()
+                    })
+            } else {
+                HashMap::new()
+            };
+            if !existing.is_empty() {
+                info!(
+                    "Adding {} assets that already exist on the target without downloading them",
+                    existing.len()
+                );
+            }
+            let order: HashMap<_, _> = missing
+                .iter()
+                .enumerate()
+                .map(|(i, asset)| (asset.id.clone(), i))
+                .collect();
+            let (mut downloads, linked): (Vec<_>, Vec<_>) = missing
+                .into_iter()
+                .partition(|asset| !existing.contains_key(&asset.id));
+            // With --pipe the assets are downloaded while they are uploaded, unless their
+            // checksums have to be verified before
+            let pipe = options.pipe && !options.verify;
+            if !pipe {
+                other
+                    .download_assets(&mut downloads, client, dir, options, &mut summary)
+                    .await?;
+                downloads.retain(|asset| asset.path.is_some());
+            }
+            // Keep the order of the source album for adding the assets
+            missing = downloads.into_iter().chain(linked).collect();
+            missing.sort_by_key(|asset| order[&asset.id]);
+            let download_failures = summary.failures.len();
+            let source = pipe.then_some(other);
+            self.upload_assets(client, &missing, &existing, source, options, &mut summary)
+                .await?;
+            let failed: HashSet<_> = summary.failures[download_failures..]
+                .iter()
+                .map(|(a, _)| &a.id)
+                .collect();
+            let synced: Vec<_> = missing
+                .iter()
+                .filter(|a| !failed.contains(&a.id))
+                .cloned()
+                .chain(added_directly)
+                .collect();
+            if let Some(cache) = cache {
+                let mut cache = cache.lock().unwrap();
+                cache.insert(&self.album.id, synced.iter().map(|a| &a.checksum));
+                cache.save()?;
+            }
+            summary.synced = synced;
+        }
+
+        if options.mirror {
+            self.remove_absent(other, options, client, &mut summary)
+                .await?;
+        }
+        // Keep the listing up to date for the following syncs of this album
+        if !summary.synced.is_empty() || summary.removed > 0 {
+            self.get_assets(client).await?;
+        }
+
+        Ok(summary)
+    }
+
+    /// Set the description of this album to the one of the other album. Targets that can't edit
+    /// the album, e.g. because they are accessed with a share link, are only warned about.
+    async fn sync_description(
+        &mut self,
+        other: &Self,
+        options: &SyncOptions,
+        client: &Client,
+    ) -> Result<()> {
+        let Some(description) = &other.album.description else {
+            return Ok(());
+        };
+        if self.album.description.as_ref() == Some(description) {
+            return Ok(());
+        }
+        if matches!(self.auth, Auth::SharedLink(_)) {
+            warn!(
+                "Can not set the description of {}, share links can not edit albums",
+                self.describe()
+            );
+            return Ok(());
+        }
+        if options.dry_run {
+            info!(
+                "Would set the description of {} to {description:?}",
+                self.describe()
+            );
+            return Ok(());
+        }
+        let req = self
+            .request(client, Method::PATCH, &format!("albums/{}", self.album.id))
+            .json(&serde_json::json!({"description": description}));
+        let res = ratelimit::send(req).await?;
+        let status = res.status();
+        if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            warn!(
+                "Can not set the description of {}, the API key is not allowed to edit it: \
+                 {status}",
+                self.describe()
+            );
+            return Ok(());
+        }
+        if !status.is_success() {
+            bail!(
+                "Setting the description of {} failed with status {status}: {}",
+                self.describe(),
+                res.text().await?
+            );
+        }
+        info!("Set the description of {}", self.describe());
+        self.album.description = Some(description.clone());
+        Ok(())
+    }
+
+    /// Report the assets of the other album that are skipped because an asset with the same
+    /// checksum already is in this album
+    fn report_present(&self, other: &Self, options: &SyncOptions, summary: &mut PairSummary) {
+        let present = other.album.present_in_other(&self.album, options.match_by);
+        if options.output == OutputFormat::Text && !options.quiet && !present.is_empty() {
+            println!("Assets that are skipped because they already are in the target:");
+            for (asset, existing) in &present {
+                println!(
+                    "{} -> {}: {} (as {})",
+                    other.album.name, self.album.name, asset.file_name, existing.file_name
+                );
+            }
+        }
+        summary.already_present = Some(
+            present
+                .into_iter()
+                .map(|(asset, existing)| SkippedAsset {
+                    file: asset.file_name.clone(),
+                    existing_file: existing.file_name.clone(),
+                })
+                .collect(),
+        );
+    }
+
+    /// Log the assets of the other album that are skipped because they are in this album under
+    /// a different file name, e.g. because they were renamed on the target
+    fn log_renamed(&self, other: &Self, match_by: MatchBy) {
+        for (asset, existing) in other.album.present_in_other(&self.album, match_by) {
+            if asset.file_name != existing.file_name {
+                debug!(
+                    "{} is already in {} as {}",
+                    asset.file_name, self.album.name, existing.file_name
+                );
+            }
+        }
+    }
+
+    /// Remove all assets from this album that are not contained in the album of the other
+    /// SharedLink. The assets stay in the library of the instance.
+    async fn remove_absent(
+        &self,
+        other: &Self,
+        options: &SyncOptions,
+        client: &Client,
+        summary: &mut PairSummary,
+    ) -> Result<()> {
+        let mut absent = self
+            .album
+            .missing_from_other(&other.album, options.match_by);
+        // Otherwise re-encoded copies would be removed and synced again by every run
+        if options.skip_existing_by_name {
+            other.album.drop_same_name_and_date(&mut absent);
+        }
+        if options.dry_run {
+            summary.would_remove = Some(absent.iter().map(|a| a.file_name.clone()).collect());
+        }
+        if absent.is_empty() {
+            return Ok(());
+        }
+        if options.dry_run {
+            if options.output == OutputFormat::Text && !options.quiet {
+                println!("Assets that would be removed:");
+                for asset in &absent {
+                    println!("{}: {}", self.album.name, asset.file_name);
+                }
+            }
+            return Ok(());
+        }
+
+        if options.confirm_above.is_some() {
+            let question = format!(
+                "Remove {} assets that are not in {} from {}",
+                absent.len(),
+                other.describe(),
+                self.describe()
+            );
+            if !confirm::ask(question).await? {
+                info!("Not removing {} assets", absent.len());
+                return Ok(());
+            }
+        }
+        info!(
+            "Removing {} assets that are not in {}",
+            absent.len(),
+            other.album.name
+        );
+        let ids: Vec<_> = absent.iter().map(|a| &a.id).collect();
+        let path = format!("albums/{}/assets", self.album.id);
+        let req = self
+            .request(client, Method::DELETE, &path)
+            .json(&serde_json::json!({"ids": ids}));
+        let res = ratelimit::send(req).await?;
+        if !res.status().is_success() {
+            bail!(
+                "Removing {} assets from {} failed with status {}: {}",
+                absent.len(),
+                self.describe(),
+                res.status(),
+                res.text().await?
+            );
+        }
+        summary.removed = absent.len();
+
+        Ok(())
+    }
+}
+
+impl Asset {
+    /// Whether the owner archived the asset
+    fn archived(&self) -> bool {
+        self.is_archived || self.visibility.as_deref() == Some("archive")
+    }
+
+    /// Size of the original file, if reported by immich
+    fn size(&self) -> Option<u64> {
+        self.exif_info.as_ref()?.file_size
+    }
+
+    /// Device asset id for uploading, derived from the checksum if immich didn't report one, so
+    /// repeated uploads of the same asset get the same id
+    fn upload_device_asset_id(&self) -> String {
+        self.device_asset_id
+            .clone()
+            .unwrap_or_else(|| format!("iss-{}", self.checksum))
+    }
+
+    /// Device id for uploading
+    fn upload_device_id(&self) -> String {
+        self.device_id.clone().unwrap_or_else(|| "iss".to_owned())
+    }
+
+    /// Creation and modification time for uploading. A missing time is replaced by the other
+    /// one, or the current time if both are missing.
+    fn upload_times(&self) -> (String, String) {
+        let created = self
+            .file_created_at
+            .as_ref()
+            .or(self.file_modified_at.as_ref());
+        let modified = self
+            .file_modified_at
+            .as_ref()
+            .or(self.file_created_at.as_ref());
+        let now = || Utc::now().to_rfc3339();
+        (
+            created.cloned().unwrap_or_else(now),
+            modified.cloned().unwrap_or_else(now),
+        )
+    }
+}
+
+impl Album {
+    /// Get all assets that are in this album but not in the other album, comparing the assets
+    /// by the given key
+    fn missing_from_other(&self, other: &Self, match_by: MatchBy) -> Vec<Asset> {
+        let other_keys: HashSet<_> = other.assets.iter().map(|a| match_by.key(a)).collect();
+        let missing_ids: Vec<Asset> = self
+            .assets
+            .iter()
+            .filter(|asset| !other_keys.contains(&match_by.key(asset)))
+            .cloned()
+            .collect();
+        missing_ids
+    }
+
+    /// Remove the assets that have the same file name and creation time as an asset of this
+    /// album, e.g. a copy that was re-encoded by the server. Returns the number of removed
+    /// assets.
+    fn drop_same_name_and_date(&self, assets: &mut Vec<Asset>) -> usize {
+        let keys: HashSet<_> = self
+            .assets
+            .iter()
+            .map(|a| MatchBy::FilenameDate.key(a))
+            .collect();
+        let count = assets.len();
+        assets.retain(|asset| !keys.contains(&MatchBy::FilenameDate.key(asset)));
+        count - assets.len()
+    }
+
+    /// Get all assets that are in this album and in the other album, paired with the asset with
+    /// the same key in the other album
+    fn present_in_other<'a>(
+        &'a self,
+        other: &'a Self,
+        match_by: MatchBy,
+    ) -> Vec<(&'a Asset, &'a Asset)> {
+        let other_assets: HashMap<_, _> =
+            other.assets.iter().map(|a| (match_by.key(a), a)).collect();
+        self.assets
+            .iter()
+            .filter_map(|asset| Some((asset, *other_assets.get(&match_by.key(asset))?)))
+            .collect()
+    }
+}
+
+/// Ids of uploaded assets that still have to be added to an album. If the upload is aborted (e.g.
+/// by an error or Ctrl-C) before they are added, they are logged when this is dropped, so they
+/// can be found in the library.
+struct PendingIds<'a> {
+    album: &'a str,
+
+    /// Ids with the position of their asset in the list of assets to add
+    ids: Vec<(usize, String)>,
+}
+
+impl Drop for PendingIds<'_> {
+    fn drop(&mut self) {
+        if !self.ids.is_empty() {
+            warn!(
+                "{} uploaded assets were not added to album {}: {}",
+                self.ids.len(),
+                self.album,
+                self.ids
+                    .iter()
+                    .map(|(_, id)| id.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+}
+
+/// Directory that downloaded assets are stored in until they are uploaded
+#[derive(Debug)]
+enum DownloadDir {
+    /// Temporary directory that is deleted when dropped
+    Temp(TempDir),
+
+    /// Directory that is kept after syncing
+    Persistent(PathBuf),
+}
+
+impl DownloadDir {
+    /// Create the directory for downloads from the given album, either below `base` or as
+    /// temporary directory below `temp_dir` or the system's temporary directory
+    fn new(base: Option<&Path>, temp_dir: Option<&Path>, album: &Album) -> Result<Self> {
+        match base {
+            Some(base) => {
+                let path = base.join(&album.name);
+                fs::create_dir_all(&path)
+                    .with_context(|| format!("Creating download directory {}", path.display()))?;
+                Ok(Self::Persistent(path))
+            }
+            None => {
+                let temp_dir = temp_dir.map_or_else(env::temp_dir, Path::to_path_buf);
+                let dir = tempfile::Builder::new()
+                    .prefix("iss")
+                    .tempdir_in(&temp_dir)
+                    .with_context(|| {
+                        format!("Creating download directory in {}", temp_dir.display())
+                    })?;
+                Ok(Self::Temp(dir))
+            }
+        }
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Self::Temp(dir) => dir.path(),
+            Self::Persistent(path) => path,
+        }
+    }
+}
+
+/// Create a temporary file in the directory that the given asset is downloaded to. It is only
+/// moved into place by `persist_download` once the download is complete, so concurrent
+/// downloads into the same directory never write to the same file.
+fn create_download_file(dir: &Path) -> Result<(tokio::fs::File, TempPath)> {
+    let (file, path) = tempfile::Builder::new()
+        .prefix(".")
+        .suffix(".part")
+        .tempfile_in(dir)?
+        .into_parts();
+    Ok((tokio::fs::File::from_std(file), path))
+}
+
+/// Put the file of an earlier download of the asset into the directory, as hard link if possible
+/// and as copy otherwise. Returns the path in the directory.
+fn link_download(file: &Path, dir: &Path, asset: &Asset) -> Result<PathBuf> {
+    let (_, download) = create_download_file(dir)?;
+    // The unique name of the empty temporary file is taken over by the link
+    fs::remove_file(&download)?;
+    if fs::hard_link(file, &download).is_err() {
+        fs::copy(file, &download)
+            .with_context(|| format!("Copying {} to {}", file.display(), dir.display()))?;
+        if let Some(modified) = &asset.file_modified_at {
+            set_file_time(&download, modified);
+        }
+    }
+    persist_download(dir, asset, download)
+}
+
+/// Explain a failed write of a download, with hints how to avoid a full disk. The partially
+/// downloaded file is removed when the download is dropped.
+fn storage_error(error: std::io::Error, dir: &Path, asset: &Asset) -> anyhow::Error {
+    if error.kind() != ErrorKind::StorageFull {
+        return error.into();
+    }
+    let size = asset
+        .size()
+        .map(|size| format!(" ({})", HumanBytes(size)))
+        .unwrap_or_default();
+    anyhow!(error).context(format!(
+        "No space left in {} for {}{size}, use --temp-dir to download to a larger disk or \
+         --max-file-size to skip large assets",
+        dir.display(),
+        asset.file_name
+    ))
+}
+
+/// Move a completed download to its final name in the directory. If a different file with the
+/// same name already exists, the id of the asset is appended to the name.
+fn persist_download(dir: &Path, asset: &Asset, download: TempPath) -> Result<PathBuf> {
+    let file_name = sanitize_file_name(&asset.file_name);
+    let path = dir.join(&file_name);
+    // Claiming the name fails atomically if another download already took it
+    let download = match download.persist_noclobber(&path) {
+        Ok(()) => return Ok(path),
+        Err(e) if e.error.kind() == ErrorKind::AlreadyExists => e.path,
+        Err(e) => return Err(e.error.into()),
+    };
+
+    let name = Path::new(&file_name);
+    let stem = name.file_stem().unwrap_or_default().to_string_lossy();
+    let unique_name = match name.extension() {
+        Some(ext) => format!("{stem}_{}.{}", asset.id, ext.to_string_lossy()),
+        None => format!("{stem}_{}", asset.id),
+    };
+    // An existing file with this name is the same asset, so it is replaced atomically
+    let path = dir.join(unique_name);
+    download.persist(&path).map_err(|e| e.error)?;
+    Ok(path)
+}
+
+/// Turn the file name of an asset into a name that is valid on all platforms and can't point
+/// outside of the download directory. Path separators, characters that are not allowed on
+/// Windows and control characters are replaced by `_`, as are names reserved by Windows.
+fn sanitize_file_name(name: &str) -> String {
+    const RESERVED: [&str; 22] = [
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    // Windows drops trailing dots and spaces, which would also turn ".." into a valid name
+    let trimmed = sanitized.trim_end_matches(['.', ' ']).len();
+    sanitized.truncate(trimmed);
+    let stem = sanitized.split('.').next().unwrap_or_default();
+    if sanitized.is_empty()
+        || RESERVED
+            .iter()
+            .any(|r| r.eq_ignore_ascii_case(stem.trim_end()))
+    {
+        sanitized.insert(0, '_');
+    }
+    sanitized
+}
+
+/// Whether a response with the given content type can be the original file of an asset
+fn is_media_type(content_type: &str) -> bool {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    let media_type = media_type.to_ascii_lowercase();
+    ["image/", "video/", "audio/"]
+        .iter()
+        .any(|prefix| media_type.starts_with(prefix))
+        || media_type == "application/octet-stream"
+}
+
+/// Path of the API below the base url of an instance, "/api" unless configured otherwise. An
+/// empty path serves the API at the root of the base url.
+fn normalize_api_path(api_path: Option<&str>) -> String {
+    match api_path.unwrap_or("api").trim_matches('/') {
+        "" => String::new(),
+        path => format!("/{path}"),
+    }
+}
+
+/// URL of the given path of the API of the instance at `base_url`
+fn api_url(base_url: &str, api_path: &str, path: &str) -> String {
+    format!("{base_url}{api_path}/{path}")
+}
+
+/// Set the access and modification time of the file to the given RFC 3339 timestamp
+fn set_file_time(path: &Path, timestamp: &str) {
+    let time = match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(time) => FileTime::from_unix_time(time.timestamp(), time.timestamp_subsec_nanos()),
+        Err(e) => {
+            debug!("Not setting file time, invalid timestamp {timestamp}: {e}");
+            return;
+        }
+    };
+    if let Err(e) = filetime::set_file_times(path, time, time) {
+        warn!("Setting file time of {} failed: {e}", path.display());
+    }
+}
+
+/// Everything that is shared between the syncs of one run
+struct Run<'a> {
+    args: &'a Args,
+    config: &'a Config,
+    options: SyncOptions,
+    cache: Option<Mutex<Cache>>,
+    client: Client,
+
+    /// States of the pairs of albums after their last complete sync, see [`PairState`]
+    pair_states: Mutex<HashMap<String, PairState>>,
+
+    /// Fingerprint of the options that decide which assets are synced, so a pair is compared
+    /// again if they change
+    fingerprint: String,
+
+    /// Connected albums of every peer, so each album is only resolved and listed once per run.
+    /// They are connected when first needed and held while a sync uses them, so parallel syncs
+    /// don't use the same album at once.
+    links: HashMap<&'a str, tokio::sync::Mutex<Option<Vec<SharedLink>>>>,
+
+    /// Plan given with --apply-plan, which is synced instead of the sync_with entries
+    plan: Option<Plan>,
+}
+
+impl Run<'_> {
+    /// Sync the albums of the peer `name` with the peer referenced by its sync_with `entry`
+    async fn sync_entry(&self, name: &str, entry: &str) -> Result<Vec<PairSummary>> {
+        let (other_name, both) = parse_sync_target(entry);
+        let bidirectional = both || self.args.bidirectional;
+
+        // Locks are always taken in the same order to avoid deadlocks
+        let (mut these, mut others) = if name < other_name {
+            let these = self.links[name].lock().await;
+            (these, self.links[other_name].lock().await)
+        } else {
+            let others = self.links[other_name].lock().await;
+            (self.links[name].lock().await, others)
+        };
+        let these = self.connect(&mut these, name).await?;
+        let others = self.connect(&mut others, other_name).await?;
+
+        let mut summaries = Vec::new();
+        for (i, j) in album_pairs(others.len(), these.len())? {
+            let (this, other) = (&mut these[j], &mut others[i]);
+            summaries.push(self.sync_pair(other_name, other, name, this).await?);
+            if bidirectional {
+                summaries.push(self.sync_pair(name, this, other_name, other).await?);
+            }
+        }
+
+        Ok(summaries)
+    }
+
+    /// Add the assets of the album `from` of the peer `from_name` to the album `to` of the peer
+    /// `to_name`. If neither album changed since the last complete sync of the pair, the assets
+    /// are not listed and compared.
+    async fn sync_pair(
+        &self,
+        from_name: &str,
+        from: &mut SharedLink,
+        to_name: &str,
+        to: &mut SharedLink,
+    ) -> Result<PairSummary> {
+        let (from_album, to_album) = (from.album.name.clone(), to.album.name.clone());
+        let skipped = |count| PairSummary {
+            from: from_name.to_owned(),
+            from_album: from_album.clone(),
+            to: to_name.to_owned(),
+            to_album: to_album.clone(),
+            considered: count,
+            skipped: count,
+            ..Default::default()
+        };
+        // An empty source has nothing to sync, unless --mirror has to empty the target
+        let empty = |from: &SharedLink| {
+            let empty = !self.options.mirror
+                && (from.album.asset_count == Some(0)
+                    || from.album.listed && from.album.assets.is_empty());
+            if empty {
+                info!(
+                    "Source album '{}' of {from_name} is empty, skipping",
+                    from.album.name
+                );
+            }
+            empty
+        };
+        if empty(from) {
+            return Ok(skipped(0));
+        }
+        let key = PairState::key(&from.album, &to.album);
+        let current = PairState::new(&from.album, &to.album, &self.fingerprint);
+        let unchanged = current
+            .is_some_and(|current| self.pair_states.lock().unwrap().get(&key) == Some(&current));
+        if self.options.quick_check && unchanged {
+            info!(
+                "Nothing changed in {} ({}) and {} ({}) since the last sync",
+                from_name, from.album.name, to_name, to.album.name,
+            );
+            return Ok(skipped(from.album.asset_count.unwrap_or_default()));
+        }
+
+        let client = &self.client;
+        from.ensure_listed(client).await?;
+        if empty(from) {
+            return Ok(skipped(0));
+        }
+        info!(
+            "Adding assets from {} ({}) to {} ({}) ...",
+            from_name, from.album.name, to_name, to.album.name,
+        );
+        to.ensure_listed(client).await?;
+        let dir = self.options.downloads.keep(DownloadDir::new(
+            self.args.download_dir.as_deref(),
+            self.args.temp_dir.as_deref(),
+            &from.album,
+        )?);
+        let cache = self.cache.as_ref();
+        let mut summary = to
+            .upload_missing(from, &self.options, cache, client, &dir)
+            .instrument(info_span!("sync", from = from_name, to = to_name))
+            .await?;
+        summary.from = from_name.to_owned();
+        summary.to = to_name.to_owned();
+        if self.options.sync_album_metadata {
+            to.sync_description(from, &self.options, client).await?;
+        }
+
+        // The target has been listed again after the sync, so its state includes the new assets
+        let complete = !self.options.dry_run && summary.failed == 0 && summary.deferred == 0;
+        if let Some(state) =
+            PairState::new(&from.album, &to.album, &self.fingerprint).filter(|_| complete)
+        {
+            if let Some(cache) = cache {
+                let mut cache = cache.lock().unwrap();
+                cache.set_pair(key.clone(), state.clone());
+                cache.save()?;
+            }
+            self.pair_states.lock().unwrap().insert(key, state);
+        }
+        Ok(summary)
+    }
+
+    /// Print the assets that would be synced in each direction for all sync_with entries,
+    /// identified by their checksums
+    async fn diff_all(&self) -> Result<()> {
+        for (name, peer) in self.config {
+            for entry in &peer.sync_with {
+                let (other_name, _) = parse_sync_target(entry);
+                let mut these = self.links[name.as_str()].lock().await;
+                let these = self.connect(&mut these, name).await?;
+                let mut others = self.links[other_name].lock().await;
+                let others = self.connect(&mut others, other_name).await?;
+                for (i, j) in album_pairs(others.len(), these.len())? {
+                    these[j].ensure_listed(&self.client).await?;
+                    others[i].ensure_listed(&self.client).await?;
+                    let (this, other) = (&these[j].album, &others[i].album);
+                    let match_by = self.options.match_by;
+                    let to_this = other.missing_from_other(this, match_by);
+                    let to_other = this.missing_from_other(other, match_by);
+                    println!("--- {other_name} ({})", other.name);
+                    println!("+++ {name} ({})", this.name);
+                    for asset in &to_this {
+                        println!("+ {}", asset.file_name);
+                    }
+                    for asset in &to_other {
+                        println!("- {}", asset.file_name);
+                    }
+                    println!(
+                        "{} only in {other_name} (-> {name}), {} only in {name} (-> {other_name}), \
+                         {} in both",
+                        to_this.len(),
+                        to_other.len(),
+                        this.present_in_other(other, match_by).len()
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sync the assets of the plan. The assets are not compared with the sync_with entries of
+    /// the config again, only assets that are already in the target album are skipped.
+    async fn apply_plan(&self, plan: &Plan, summaries: &mut Vec<PairSummary>) -> Result<()> {
+        let client = &self.client;
+        for pair in &plan.pairs {
+            // Locks are always taken in the same order to avoid deadlocks
+            let (mut these, mut others) = if pair.to < pair.from {
+                let these = self.links[pair.to.as_str()].lock().await;
+                (these, self.links[pair.from.as_str()].lock().await)
+            } else {
+                let others = self.links[pair.from.as_str()].lock().await;
+                (self.links[pair.to.as_str()].lock().await, others)
+            };
+            let these = self.connect(&mut these, &pair.to).await?;
+            let others = self.connect(&mut others, &pair.from).await?;
+            let to = these.iter_mut().find(|link| link.album.id == pair.to_album);
+            let from = others
+                .iter_mut()
+                .find(|link| link.album.id == pair.from_album);
+            let (Some(from), Some(to)) = (from, to) else {
+                warn!(
+                    "Album {} of {} or album {} of {} is not accessible anymore, skipping {} planned assets",
+                    pair.from_album,
+                    pair.from,
+                    pair.to_album,
+                    pair.to,
+                    pair.assets.len()
+                );
+                continue;
+            };
+
+            from.ensure_listed(client).await?;
+            let planned: HashSet<_> = pair
+                .assets
+                .iter()
+                .map(|asset| (asset.id.as_str(), asset.checksum.as_str()))
+                .collect();
+            from.album
+                .assets
+                .retain(|asset| planned.contains(&(asset.id.as_str(), asset.checksum.as_str())));
+            // Only a part of the album is left, so it has to be listed again for other pairs
+            from.album.listed = false;
+            let gone = pair.assets.len() - from.album.assets.len();
+            if gone > 0 {
+                warn!(
+                    "{gone} planned assets are not in {} ({}) anymore or changed, skipping them",
+                    pair.from, from.album.name
+                );
+            }
+            info!(
+                "Adding planned assets from {} ({}) to {} ({}) ...",
+                pair.from, from.album.name, pair.to, to.album.name,
+            );
+            to.ensure_listed(client).await?;
+            let dir = self.options.downloads.keep(DownloadDir::new(
+                self.args.download_dir.as_deref(),
+                self.args.temp_dir.as_deref(),
+                &from.album,
+            )?);
+            let mut summary = to
+                .upload_missing(from, &self.options, self.cache.as_ref(), client, &dir)
+                .instrument(info_span!("sync", from = pair.from, to = pair.to))
+                .await?;
+            summary.from = pair.from.clone();
+            summary.to = pair.to.clone();
+            summaries.push(summary);
+        }
+        Ok(())
+    }
+
+    /// Get the connected albums of the peer, connecting them if this is their first use
+    async fn connect<'l>(
+        &self,
+        links: &'l mut Option<Vec<SharedLink>>,
+        name: &str,
+    ) -> Result<&'l mut Vec<SharedLink>> {
+        if links.is_none() {
+            let peer = &self.config[name];
+            let concurrency = peer.concurrency.unwrap_or(self.args.concurrency);
+            let albums = peer.albums()?;
+            *links = Some(connect_albums(&albums, name, concurrency, &self.client, false).await?);
+        }
+        Ok(links.as_mut().expect("links are connected"))
+    }
+
+    /// Sync all sync_with entries of the config once
+    async fn sync_all(&self) -> Result<()> {
+        let started = Utc::now();
+        let timer = Instant::now();
+        let mut summaries = Vec::new();
+        let mut result = self.sync_pairs(&mut summaries, timer).await;
+        // The files may change until the next run
+        self.options.downloads.clear();
+        // Aborted runs are recorded as well, so their failure is visible and the metrics of the
+        // last successful run don't remain
+        if let Some(path) = &self.args.report {
+            let error = result.as_ref().err().map(|e| format!("{e:#}"));
+            let run = report::RunReport::new(started, timer.elapsed(), self.args.dry_run, error);
+            let written = run
+                .append(path, &summaries)
+                .with_context(|| format!("Writing report {}", path.display()));
+            result = first_error(result, written);
+        }
+        if let Some(path) = &self.args.metrics_file {
+            let written =
+                metrics::write(path, started, timer.elapsed(), result.is_ok(), &summaries);
+            result = first_error(result, written);
+        }
+        if let Some(url) = &self.args.notify {
+            notify::send(&self.client, url, self.args.dry_run, &summaries, &result).await;
+        }
+        result
+    }
+
+    /// Sync all sync_with entries of the config and collect the results in `summaries`. The run
+    /// started at `timer`.
+    async fn sync_pairs(&self, summaries: &mut Vec<PairSummary>, timer: Instant) -> Result<()> {
+        // The albums may have changed since the last run
+        for links in self.links.values() {
+            *links.lock().await = None;
+        }
+        let mut dead_links = 0;
+        if let Some(plan) = &self.plan {
+            self.apply_plan(plan, summaries).await?;
+        } else {
+            let entries = config::sync_order(self.config, self.args.bidirectional);
+            let mut syncs = stream::iter(entries)
+                .map(|(name, entry)| self.sync_entry(name, entry))
+                .buffer_unordered(self.args.peer_concurrency);
+            while let Some(result) = syncs.next().await {
+                match result {
+                    Ok(pairs) => summaries.extend(pairs),
+                    // A dead link only affects the entries of its peer, so the others are still
+                    // synced
+                    Err(e) if matches!(e.downcast_ref(), Some(IssError::DeadLink { .. })) => {
+                        error!("{e:#}");
+                        dead_links += 1;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        if let Some(path) = &self.args.plan_out {
+            let plan = Plan::new(summaries);
+            plan.write(path)?;
+            info!(
+                "Wrote the plan for {} assets to {}",
+                plan.pairs
+                    .iter()
+                    .map(|pair| pair.assets.len())
+                    .sum::<usize>(),
+                path.display()
+            );
+        }
+
+        // Printed even with --quiet, but not into the JSON output
+        if self.args.stats {
+            let stats = stats::take(timer.elapsed());
+            if self.args.output == OutputFormat::Json {
+                eprint!("{stats}");
+            } else {
+                print!("{stats}");
+            }
+        }
+        let summary = RunSummary::new(self.args.dry_run, summaries);
+        if self.args.output == OutputFormat::Json {
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+        } else if self.args.quiet {
+            print!("{}", summary.to_text());
+        }
+
+        let failures: Vec<_> = summaries.iter().flat_map(|s| &s.failures).collect();
+        if !failures.is_empty() {
+            error!("{} assets failed to sync:", failures.len());
+            for (asset, e) in &failures {
+                error!(id = %asset.id, file = %asset.file_name, "{e:#}");
+            }
+            return Err(IssError::AssetsFailed {
+                count: failures.len(),
+            }
+            .into());
+        }
+        if dead_links > 0 && summaries.is_empty() {
+            bail!("All sync_with entries were skipped because of dead share links");
+        }
+        if dead_links > 0 {
+            return Err(IssError::EntriesSkipped { count: dead_links }.into());
+        }
+        self.options.state.finish()?;
+
+        Ok(())
+    }
+}
+
+/// Combine the result of a run with the result of an operation after it, e.g. writing its
+/// metrics. An error of the run takes precedence and the other one is only logged.
+fn first_error(result: Result<()>, after: Result<()>) -> Result<()> {
+    match (result, after) {
+        (Err(e), Err(after)) => {
+            error!("{after:#}");
+            Err(e)
+        }
+        (Err(e), Ok(())) => Err(e),
+        (Ok(()), after) => after,
+    }
+}
+
+/// Connect to all given albums of the peer and fetch their assets if `list` is set
+async fn connect_albums(
+    sources: &[AlbumSource<'_>],
+    peer: &str,
+    concurrency: usize,
+    client: &Client,
+    list: bool,
+) -> Result<Vec<SharedLink>> {
+    let mut links = Vec::new();
+    for source in sources {
+        let mut link = SharedLink::from_source(source, peer, concurrency, client).await?;
+        if list {
+            link.get_assets(client).await?;
+        }
+        links.push(link);
+    }
+    Ok(links)
+}
+
+/// Fingerprint of the options that decide which assets of a pair of albums are synced
+fn options_fingerprint(args: &Args) -> String {
+    let options = format!(
+        "{:?}",
+        (
+            args.since,
+            args.until,
+            args.only,
+            &args.skip,
+            &args.include,
+            &args.exclude,
+            args.max_file_size,
+            args.min_file_size,
+            args.include_archived,
+            args.match_by,
+            args.skip_existing_by_name,
+            args.mirror,
+        )
+    );
+    BASE64.encode(Sha1::digest(options))
+}
+
+/// Print the albums of the peer with the number and size of their assets
+async fn list_peer(config: &Config, name: &str, client: &Client, assets: bool) -> Result<()> {
+    let peer = config
+        .get(name)
+        .with_context(|| format!("Unknown peer {name}"))?;
+    let links = connect_albums(&peer.albums()?, name, 1, client, true).await?;
+    for link in links {
+        let album = &link.album;
+        let size = album.assets.iter().filter_map(Asset::size).sum();
+        let unknown = album.assets.iter().filter(|a| a.size().is_none()).count();
+        let mut line = format!(
+            "{} ({}): {} assets, {}",
+            album.name,
+            album.id,
+            album.assets.len(),
+            HumanBytes(size)
+        );
+        if unknown > 0 {
+            line.push_str(&format!(" (size of {unknown} assets unknown)"));
+        }
+        println!("{line}");
+        if assets {
+            for asset in &album.assets {
+                println!("  {} {}", asset.file_name, asset.checksum);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Remove the entries from the cache whose checksum is not in any album of the config
+async fn prune_cache(config: &Config, path: &Path, client: &Client) -> Result<()> {
+    let mut links = Vec::new();
+    for (name, peer) in config {
+        // Pruning with an incomplete list of assets would drop entries that are still needed
+        let albums = connect_albums(&peer.albums()?, name, 1, client, true)
+            .await
+            .with_context(|| format!("Can not prune the cache without the albums of {name}"))?;
+        links.extend(albums);
+    }
+    let present: HashSet<_> = links
+        .iter()
+        .flat_map(|link| &link.album.assets)
+        .map(|asset| &asset.checksum)
+        .collect();
+
+    let size = |path: &Path| fs::metadata(path).map(|m| m.len()).unwrap_or_default();
+    let before = size(path);
+    let mut cache = Cache::load(path)?;
+    let pruned = cache.prune(&present);
+    cache.save()?;
+    let reclaimed = before.saturating_sub(size(path));
+    info!(
+        "Pruned {pruned} cache entries, reclaimed {}",
+        HumanBytes(reclaimed)
+    );
+    Ok(())
+}
+
+/// Load the certificates from a PEM file, which may contain multiple certificates, or a DER file
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let raw = fs::read(path).with_context(|| format!("Reading {}", path.display()))?;
+    let certs = if raw.starts_with(b"-----BEGIN") {
+        Certificate::from_pem_bundle(&raw)
+    } else {
+        Certificate::from_der(&raw).map(|cert| vec![cert])
+    };
+    certs.with_context(|| format!("Invalid certificate {}", path.display()))
+}
+
+/// Exit code for invalid arguments or an invalid config, before anything was synced
+const EXIT_USAGE: u8 = 1;
+
+/// Exit code for a run that completed, but in which some assets or sync_with entries failed
+const EXIT_PARTIAL_FAILURE: u8 = 2;
+
+/// Exit code for a run that was aborted
+const EXIT_FAILURE: u8 = 3;
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(e) => {
+            // Clap would exit with 2, which is reserved for partial failures
+            let _ = e.print();
+            return if e.use_stderr() {
+                ExitCode::from(EXIT_USAGE)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    let level = match args.verbose {
+        0 if args.quiet => Level::WARN,
+        0 => Level::INFO,
+        1 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_target(false)
+        .with_writer(|| progress::LogWriter)
+        .init();
+    if args.quiet {
+        progress::hide();
+    }
+
+    let (config, client, options, plan) = match setup(&args).await {
+        Ok(setup) => setup,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+    match run(&args, &config, client, options, plan).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            let partial = matches!(
+                e.downcast_ref(),
+                Some(IssError::AssetsFailed { .. } | IssError::EntriesSkipped { .. })
+            );
+            ExitCode::from(if partial {
+                EXIT_PARTIAL_FAILURE
+            } else {
+                EXIT_FAILURE
+            })
+        }
+    }
+}
+
+/// Validate the arguments, build the HTTP client and load the config and plan
+async fn setup(args: &Args) -> Result<(Config, Client, SyncOptions, Option<Plan>)> {
+    if args.concurrency < 1 {
+        bail!("--concurrency must be at least 1");
+    }
+    if args.download_concurrency == Some(0) {
+        bail!("--download-concurrency must be at least 1");
+    }
+    if args.upload_concurrency == Some(0) {
+        bail!("--upload-concurrency must be at least 1");
+    }
+    if args.retries < 1 {
+        bail!("--retries must be at least 1");
+    }
+    if args.limit == Some(0) {
+        bail!("--limit must be at least 1");
+    }
+    if args.album_batch_size < 1 {
+        bail!("--album-batch-size must be at least 1");
+    }
+    if args.quality != Quality::Original {
+        let quality = args.quality.to_possible_value().expect("no skipped values");
+        let quality = quality.get_name();
+        // The uploaded versions have different checksums than the originals, so they would
+        // never be recognized as already synced
+        if args.match_by == MatchBy::Checksum {
+            bail!("--quality {quality} requires --match-by filename or filename+date");
+        }
+        if args.verify_upload {
+            bail!("--verify-upload can only be used with --quality original");
+        }
+        warn!(
+            "Syncing {quality} versions of the assets, which lack most of the metadata of the \
+             originals, don't use them as backup"
+        );
+    }
+    if args.pipe && !args.no_verify {
+        warn!(
+            "--pipe only streams the assets with --no-verify, they are downloaded first to verify \
+             their checksums"
+        );
+    }
+    if args.peer_concurrency < 1 {
+        bail!("--peer-concurrency must be at least 1");
+    }
+    if args.interval.is_some_and(|interval| interval.is_zero()) {
+        bail!("--interval must be greater than 0");
+    }
+    if let Some(rate) = args.rate_limit {
+        if rate.is_nan() || rate <= 0.0 {
+            bail!("--rate-limit must be greater than 0");
+        }
+        ratelimit::init(rate);
+    }
+    if let Some(dir) = &args.temp_dir {
+        // Fail before anything is downloaded if the directory can't be used
+        tempfile::tempfile_in(dir)
+            .with_context(|| format!("--temp-dir {} is not a writable directory", dir.display()))?;
+    }
+    if let Some(url) = &args.notify {
+        reqwest::Url::parse(url).with_context(|| format!("Invalid --notify URL {url}"))?;
+    }
+    let max_concurrency = [args.download_concurrency, args.upload_concurrency]
+        .into_iter()
+        .flatten()
+        .fold(args.concurrency, usize::max);
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(args.connect_timeout))
+        .read_timeout(Duration::from_secs(args.timeout))
+        .pool_max_idle_per_host(max_concurrency * args.peer_concurrency)
+        .user_agent(&args.user_agent);
+    if let Some(path) = &args.ca_cert {
+        for cert in load_certificates(path)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    // Without an explicit proxy, reqwest uses the proxies from the environment
+    if let Some(proxy) = &args.proxy {
+        let proxy = Proxy::all(proxy).with_context(|| format!("Invalid proxy {proxy}"))?;
+        builder = builder.proxy(proxy);
+    }
+    if args.danger_accept_invalid_certs {
+        warn!("Accepting invalid TLS certificates, connections are not secure");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    let client = builder.build()?;
+    let mut config = match (&args.from, &args.to) {
+        (Some(from), Some(to)) => config::one_shot(from, to),
+        _ if !args.config.is_empty() => {
+            config::load(&args.config, args.config_format, &client).await?
+        }
+        _ => bail!("Either --config or --from and --to have to be given"),
+    };
+    config::expand_wildcards(&mut config)?;
+    config::validate(&config, args.bidirectional)?;
+    if !args.group.is_empty() {
+        config::select_groups(&mut config, &args.group)?;
+    }
+    if args.no_add_to_album {
+        for (name, entry) in config::sync_order(&config, args.bidirectional) {
+            let (other, both) = parse_sync_target(entry);
+            let targets = [Some(name), (both || args.bidirectional).then_some(other)];
+            // Uploads with a share link are only visible through its album
+            if let Some(target) = targets
+                .into_iter()
+                .flatten()
+                .find(|target| config[*target].shared_link.is_some())
+            {
+                bail!(
+                    "--no-add-to-album requires an API key for peer {target}, which receives assets with a share link"
+                );
+            }
+        }
+    }
+    let plan = args.apply_plan.as_deref().map(Plan::load).transpose()?;
+    if let Some(plan) = &plan {
+        plan.validate(&config)?;
+    }
+    Ok((config, client, SyncOptions::try_from(args)?, plan))
+}
+
+/// Run the action selected by the arguments, by default syncing all sync_with entries once or
+/// every `--interval`
+async fn run(
+    args: &Args,
+    config: &Config,
+    client: Client,
+    options: SyncOptions,
+    plan: Option<Plan>,
+) -> Result<()> {
+    if let Some(peer) = &args.list {
+        return list_peer(config, peer, &client, args.list_assets).await;
+    }
+    if let Some(path) = args.cache.as_deref().filter(|_| args.prune_cache) {
+        return prune_cache(config, path, &client).await;
+    }
+    let cache = args.cache.as_deref().map(Cache::load).transpose()?;
+    let run = Run {
+        args,
+        config,
+        options,
+        pair_states: Mutex::new(
+            cache
+                .as_ref()
+                .map(|c| c.pairs().clone())
+                .unwrap_or_default(),
+        ),
+        fingerprint: options_fingerprint(args),
+        cache: cache.map(Mutex::new),
+        client,
+        links: config
+            .keys()
+            .map(|name| (name.as_str(), Default::default()))
+            .collect(),
+        plan,
+    };
+
+    if args.dry_run_diff {
+        return run.diff_all().await;
+    }
+
+    let Some(interval) = args.interval else {
+        return tokio::select! {
+            result = run.sync_all() => result,
+            _ = tokio::signal::ctrl_c() => bail!(INTERRUPTED),
+        };
+    };
+
+    let terminate = sigterm();
+    tokio::pin!(terminate);
+    let mut terminating = false;
+    loop {
+        info!("Starting sync");
+        let cycle = run.sync_all();
+        tokio::pin!(cycle);
+        let result = loop {
+            tokio::select! {
+                result = &mut cycle => break result,
+                _ = &mut terminate, if !terminating => {
+                    info!("Received SIGTERM, exiting after the current sync");
+                    terminating = true;
+                }
+                _ = tokio::signal::ctrl_c() => bail!(INTERRUPTED),
+            }
+        };
+        match result {
+            Ok(()) => info!("Sync finished"),
+            Err(e) => error!("Sync failed: {e:#}"),
+        }
+        if terminating {
+            return Ok(());
+        }
+
+        info!("Next sync in {}", humantime::format_duration(interval));
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = &mut terminate => {
+                info!("Received SIGTERM, exiting");
+                return Ok(());
+            }
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+/// Resolves when the process receives SIGTERM, never on platforms without SIGTERM
+async fn sigterm() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+                return;
+            }
+            Err(e) => warn!("Can not listen for SIGTERM: {e}"),
+        }
+    }
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_string_contains, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn asset_json(id: usize) -> serde_json::Value {
+        serde_json::json!({
+            "id": format!("asset-{id}"),
+            "checksum": format!("checksum-{id}"),
+            "originalFileName": format!("IMG_{id}.jpg"),
+            "deviceAssetId": format!("device-asset-{id}"),
+            "deviceId": "device",
+            "fileCreatedAt": "2024-01-01T00:00:00.000Z",
+            "fileModifiedAt": "2024-01-01T00:00:00.000Z",
+            "type": "IMAGE",
+        })
+    }
+
+    fn asset(id: usize) -> Asset {
+        serde_json::from_value(asset_json(id)).unwrap()
+    }
+
+    fn link(server: &MockServer) -> SharedLink {
+        SharedLink {
+            album: Album {
+                name: "Album".to_owned(),
+                id: "album".to_owned(),
+                updated_at: None,
+                asset_count: None,
+                description: None,
+                assets: Vec::new(),
+                listed: false,
+            },
+            auth: Auth::SharedLink("key".to_owned()),
+            base_url: server.uri(),
+            api_path: "/api".to_owned(),
+            concurrency: 1,
+            peer: "peer".to_owned(),
+            allow_upload: true,
+            asset_share: false,
+            resumable: Default::default(),
+        }
+    }
+
+    fn options() -> SyncOptions {
+        SyncOptions {
+            dry_run: false,
+            retries: 1,
+            verify: true,
+            verify_upload: false,
+            pipe: false,
+            resumable_min_size: None,
+            checksum_algorithm: ChecksumAlgorithm::Sha1,
+            match_by: MatchBy::Checksum,
+            skip_existing_by_name: false,
+            quality: Quality::Original,
+            download_concurrency: None,
+            upload_concurrency: None,
+            bulk_check: true,
+            quick_check: true,
+            continue_on_error: false,
+            filter: Filter::default(),
+            output: OutputFormat::Text,
+            quiet: false,
+            mirror: false,
+            sync_album_metadata: false,
+            add_to_album: true,
+            confirm_above: None,
+            album_batch_size: 500,
+            force: false,
+            limit: None,
+            order: SyncOrder::Oldest,
+            report_skipped: false,
+            progress: Arc::new(progress::Terminal),
+            downloads: Downloads::default(),
+            state: State::default(),
+        }
+    }
+
+    fn album(assets: Vec<Asset>) -> Album {
+        Album {
+            name: "Album".to_owned(),
+            id: "album".to_owned(),
+            updated_at: None,
+            asset_count: None,
+            description: None,
+            assets,
+            listed: true,
+        }
+    }
+
+    fn ids(assets: &[Asset]) -> Vec<&str> {
+        assets.iter().map(|a| a.id.as_str()).collect()
+    }
+
+    #[test]
+    fn missing_from_other_identical_albums() {
+        let this = album(vec![asset(1), asset(2)]);
+        let other = album(vec![asset(1), asset(2)]);
+
+        assert!(
+            this.missing_from_other(&other, MatchBy::Checksum)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn missing_from_other_disjoint_albums() {
+        let this = album(vec![asset(1), asset(2)]);
+        let other = album(vec![asset(3)]);
+
+        assert_eq!(
+            ids(&this.missing_from_other(&other, MatchBy::Checksum)),
+            ["asset-1", "asset-2"]
+        );
+    }
+
+    #[test]
+    fn missing_from_other_partial_overlap_by_checksum() {
+        let this = album(vec![asset(1), asset(2), asset(3)]);
+        // The same content can have a different id on another instance
+        let mut copy = asset(2);
+        copy.id = "copy-of-asset-2".to_owned();
+        let other = album(vec![copy, asset(4)]);
+
+        assert_eq!(
+            ids(&this.missing_from_other(&other, MatchBy::Checksum)),
+            ["asset-1", "asset-3"]
+        );
+    }
+
+    #[test]
+    fn missing_from_other_same_file_name_different_checksum() {
+        let this = album(vec![asset(1)]);
+        let mut edited = asset(1);
+        edited.checksum = "checksum-of-edited-copy".to_owned();
+        let other = album(vec![edited]);
+
+        assert_eq!(
+            ids(&this.missing_from_other(&other, MatchBy::Checksum)),
+            ["asset-1"]
+        );
+    }
+
+    #[test]
+    fn missing_from_other_by_filename() {
+        let this = album(vec![asset(1), asset(2)]);
+        let mut reencoded = asset(1);
+        reencoded.checksum = "checksum-of-reencoded-copy".to_owned();
+        let other = album(vec![reencoded]);
+
+        assert_eq!(
+            ids(&this.missing_from_other(&other, MatchBy::Filename)),
+            ["asset-2"]
+        );
+    }
+
+    #[test]
+    fn drop_same_name_and_date_keeps_other_photos() {
+        let mut reencoded = asset(1);
+        reencoded.checksum = "checksum-of-reencoded-copy".to_owned();
+        let mut other_photo = asset(2);
+        other_photo.file_created_at = Some("2023-05-05T10:00:00.000Z".to_owned());
+        let target = album(vec![reencoded, other_photo]);
+        let mut missing = vec![asset(1), asset(2), asset(3)];
+
+        assert_eq!(target.drop_same_name_and_date(&mut missing), 1);
+        assert_eq!(ids(&missing), ["asset-2", "asset-3"]);
+    }
+
+    #[test]
+    fn missing_from_other_by_filename_and_date() {
+        let this = album(vec![asset(1), asset(2)]);
+        let mut reencoded = asset(1);
+        reencoded.checksum = "checksum-of-reencoded-copy".to_owned();
+        // A different photo that happens to have the same name
+        let mut other_photo = asset(2);
+        other_photo.checksum = "checksum-of-other-photo".to_owned();
+        other_photo.file_created_at = Some("2023-05-05T10:00:00.000Z".to_owned());
+        let other = album(vec![reencoded, other_photo]);
+
+        assert_eq!(
+            ids(&this.missing_from_other(&other, MatchBy::FilenameDate)),
+            ["asset-2"]
+        );
+        assert!(
+            this.missing_from_other(&other, MatchBy::Filename)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn sync_order_places_invalid_dates_last() {
+        let mut assets: Vec<_> = (1..=4).map(asset).collect();
+        assets[0].file_created_at = None;
+        assets[1].file_created_at = Some("2024-03-01T00:00:00.000Z".to_owned());
+        assets[2].file_created_at = Some("yesterday".to_owned());
+        assets[3].file_created_at = Some("2023-03-01T00:00:00+02:00".to_owned());
+
+        SyncOrder::Oldest.sort(&mut assets);
+        assert_eq!(ids(&assets), ["asset-4", "asset-2", "asset-1", "asset-3"]);
+        SyncOrder::Newest.sort(&mut assets);
+        assert_eq!(ids(&assets), ["asset-2", "asset-4", "asset-1", "asset-3"]);
+    }
+
+    #[tokio::test]
+    async fn get_assets_follows_pages() {
+        let server = MockServer::start().await;
+        let first: Vec<_> = (0..PAGE_SIZE).map(asset_json).collect();
+        Mock::given(method("GET"))
+            .and(path("/api/albums/album"))
+            .and(query_param("page", "1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "assets": first,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/albums/album"))
+            .and(query_param("page", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "assets": [asset_json(PAGE_SIZE)],
+            })))
+            .mount(&server)
+            .await;
+
+        let mut link = link(&server);
+        link.get_assets(&Client::new()).await.unwrap();
+
+        assert_eq!(link.album.assets.len(), PAGE_SIZE + 1);
+        assert_eq!(
+            link.album.assets[PAGE_SIZE].id,
+            format!("asset-{PAGE_SIZE}")
+        );
+    }
+
+    #[tokio::test]
+    async fn get_assets_fails_on_rejected_page() {
+        let server = MockServer::start().await;
+        let first: Vec<_> = (0..PAGE_SIZE).map(asset_json).collect();
+        Mock::given(method("GET"))
+            .and(path("/api/albums/album"))
+            .and(query_param("page", "1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "assets": first,
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/albums/album"))
+            .and(query_param("page", "2"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+
+        let mut link = link(&server);
+        let error = link.get_assets(&Client::new()).await.unwrap_err();
+
+        assert!(
+            error.to_string().contains("failed: 403 Forbidden"),
+            "{error}"
+        );
+        assert!(!link.album.listed);
+    }
+
+    #[tokio::test]
+    async fn download_assets_keeps_assets_with_same_file_name() {
+        let server = MockServer::start().await;
+        let mut assets = vec![asset(1), asset(2)];
+        for asset in &mut assets {
+            let body = format!("content of {}", asset.id);
+            asset.file_name = "IMG_0001.jpg".to_owned();
+            asset.checksum = BASE64.encode(Sha1::digest(&body));
+            Mock::given(method("GET"))
+                .and(path(format!("/api/assets/{}/original", asset.id)))
+                .respond_with(ResponseTemplate::new(200).set_body_raw(body, "image/jpeg"))
+                .mount(&server)
+                .await;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut summary = PairSummary::default();
+        link(&server)
+            .download_assets(
+                &mut assets,
+                &Client::new(),
+                dir.path(),
+                &options(),
+                &mut summary,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(summary.downloaded, 2);
+        assert!(summary.failures.is_empty());
+        let first = assets[0].path.clone().unwrap();
+        let second = assets[1].path.clone().unwrap();
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(first).unwrap(), "content of asset-1");
+        assert_eq!(fs::read_to_string(second).unwrap(), "content of asset-2");
+    }
+
+    /// Progress that counts the reported events
+    #[derive(Default)]
+    struct Counter {
+        bytes: AtomicU64,
+        done: AtomicU64,
+    }
+
+    impl Progress for Arc<Counter> {
+        fn batch(&self, _transfer: Transfer, _len: usize) -> Box<dyn BatchProgress> {
+            Box::new(self.clone())
+        }
+    }
+
+    impl BatchProgress for Arc<Counter> {
+        fn bytes(&self, bytes: u64) {
+            self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+
+        fn done(&self, _asset: &Asset, _size: u64, _duration: Duration) {
+            self.done.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn download_assets_reports_progress() {
+        let server = MockServer::start().await;
+        let body = "content of asset-1";
+        let mut assets = vec![asset(1)];
+        assets[0].checksum = BASE64.encode(Sha1::digest(body));
+        Mock::given(method("GET"))
+            .and(path("/api/assets/asset-1/original"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "image/jpeg"))
+            .mount(&server)
+            .await;
+
+        let counter = Arc::new(Counter::default());
+        let options = SyncOptions {
+            progress: Arc::new(counter.clone()),
+            ..options()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        link(&server)
+            .download_assets(
+                &mut assets,
+                &Client::new(),
+                dir.path(),
+                &options,
+                &mut PairSummary::default(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(counter.bytes.load(Ordering::Relaxed), body.len() as u64);
+        assert_eq!(counter.done.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn download_assets_reuses_files_with_same_checksum() {
+        let server = MockServer::start().await;
+        let body = "content of asset-1";
+        Mock::given(method("GET"))
+            .and(path("/api/assets/asset-1/original"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "image/jpeg"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // The same photo in the albums of two peers, e.g. for two targets of a hub
+        let options = options();
+        let base = tempfile::tempdir().unwrap();
+        let mut paths = Vec::new();
+        let mut summaries = Vec::new();
+        for (id, album) in [(1, "First"), (2, "Second")] {
+            let mut assets = vec![asset(id)];
+            assets[0].checksum = BASE64.encode(Sha1::digest(body));
+            let album = Album {
+                name: album.to_owned(),
+                ..link(&server).album
+            };
+            let dir = DownloadDir::new(Some(base.path()), None, &album).unwrap();
+            let dir = options.downloads.keep(dir);
+            let mut summary = PairSummary::default();
+            link(&server)
+                .download_assets(&mut assets, &Client::new(), &dir, &options, &mut summary)
+                .await
+                .unwrap();
+            assert!(summary.failures.is_empty());
+            paths.push(assets[0].path.clone().unwrap());
+            summaries.push(summary);
+        }
+
+        // Both download directories contain the file, but it was only downloaded once
+        assert_eq!(paths[0], base.path().join("First/IMG_1.jpg"));
+        assert_eq!(paths[1], base.path().join("Second/IMG_2.jpg"));
+        for path in &paths {
+            assert_eq!(fs::read_to_string(path).unwrap(), body);
+        }
+        assert_eq!((summaries[0].downloaded, summaries[0].reused), (1, 0));
+        assert_eq!((summaries[1].downloaded, summaries[1].reused), (0, 1));
+        assert_eq!(summaries[1].bytes, 0);
+    }
+
+    #[test]
+    fn cache_only_reports_assets_in_target_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut cache = Cache::load(&dir.path().join("cache.json")).unwrap();
+        let checksums = ["checksum-1".to_owned(), "checksum-2".to_owned()];
+        cache.insert("album", &checksums);
+        // Asset 2 was removed from the album since it was synced
+        let album = Album {
+            name: "Album".to_owned(),
+            id: "album".to_owned(),
+            updated_at: None,
+            asset_count: None,
+            description: None,
+            assets: vec![asset(1)],
+            listed: true,
+        };
+
+        assert_eq!(
+            cache.synced(&album),
+            HashSet::from(["checksum-1".to_owned()])
+        );
+        let album = Album {
+            assets: vec![asset(1), asset(2)],
+            ..album
+        };
+        assert_eq!(
+            cache.synced(&album),
+            HashSet::from(["checksum-1".to_owned()])
+        );
+    }
+
+    #[test]
+    fn minimal_asset_gets_upload_defaults() {
+        let asset: Asset = serde_json::from_value(serde_json::json!({
+            "id": "asset-1",
+            "checksum": "checksum-1",
+            "originalFileName": "IMG_1.jpg",
+            "fileModifiedAt": "2024-01-01T00:00:00.000Z",
+        }))
+        .unwrap();
+
+        assert_eq!(asset.asset_type, AssetType::Other);
+        assert_eq!(asset.upload_device_asset_id(), "iss-checksum-1");
+        assert_eq!(asset.upload_device_id(), "iss");
+        let modified = "2024-01-01T00:00:00.000Z".to_owned();
+        assert_eq!(asset.upload_times(), (modified.clone(), modified));
+        assert_eq!(
+            asset.upload_device_asset_id(),
+            asset.upload_device_asset_id()
+        );
+    }
+
+    #[test]
+    fn sanitize_file_name_replaces_unsafe_names() {
+        assert_eq!(sanitize_file_name("IMG_0001.jpg"), "IMG_0001.jpg");
+        assert_eq!(sanitize_file_name("../../etc/passwd"), ".._.._etc_passwd");
+        assert_eq!(sanitize_file_name("..\\boot.ini"), ".._boot.ini");
+        assert_eq!(sanitize_file_name("/absolute.jpg"), "_absolute.jpg");
+        assert_eq!(sanitize_file_name("C:photo.jpg"), "C_photo.jpg");
+        assert_eq!(sanitize_file_name("what?*<>|\"x\".png"), "what______x_.png");
+        assert_eq!(sanitize_file_name("tab\there\n.jpg"), "tab_here_.jpg");
+        assert_eq!(sanitize_file_name(".."), "_");
+        assert_eq!(sanitize_file_name(""), "_");
+        assert_eq!(sanitize_file_name("photo.jpg. "), "photo.jpg");
+        assert_eq!(sanitize_file_name("con.jpg"), "_con.jpg");
+        assert_eq!(sanitize_file_name("NUL"), "_NUL");
+        assert_eq!(sanitize_file_name("console.jpg"), "console.jpg");
+        assert_eq!(sanitize_file_name("日本.heic"), "日本.heic");
+    }
+
+    #[tokio::test]
+    async fn download_assets_stays_in_directory() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/assets/asset-1/original"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("content", "image/jpeg"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let download_dir = dir.path().join("downloads");
+        fs::create_dir(&download_dir).unwrap();
+        let mut assets = vec![asset(1)];
+        assets[0].file_name = "../escaped.jpg".to_owned();
+        let mut summary = PairSummary::default();
+        let options = SyncOptions {
+            verify: false,
+            ..options()
+        };
+        link(&server)
+            .download_assets(
+                &mut assets,
+                &Client::new(),
+                &download_dir,
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap();
+
+        let downloaded = assets[0].path.clone().unwrap();
+        assert_eq!(downloaded, download_dir.join(".._escaped.jpg"));
+        assert!(!dir.path().join("escaped.jpg").exists());
+        // The original name is still used for the upload
+        assert_eq!(assets[0].file_name, "../escaped.jpg");
+    }
+
+    #[test]
+    fn select_groups_keeps_entries_within_groups() {
+        let mut config = config::parse(
+            r#"
+            [Family]
+            shared_link = "https://foo.org/share/family"
+            sync_with = ["Friends", "Grandma:both"]
+            groups = ["family"]
+
+            [Grandma]
+            shared_link = "https://foo.org/share/grandma"
+            sync_with = []
+            groups = ["family", "old"]
+
+            [Friends]
+            shared_link = "https://foo.org/share/friends"
+            sync_with = ["Family"]
+            "#,
+            ConfigFormat::Toml,
+        )
+        .unwrap();
+
+        config::select_groups(&mut config, &["family".to_owned()]).unwrap();
+        let mut names: Vec<_> = config.keys().collect();
+        names.sort();
+        assert_eq!(names, ["Family", "Grandma"]);
+        assert_eq!(config["Family"].sync_with, ["Grandma:both"]);
+
+        let error = config::select_groups(&mut config, &["work".to_owned()]).unwrap_err();
+        assert!(
+            error.to_string().contains("No peer is in group work"),
+            "{error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn config_directory_rejects_duplicate_peers() {
+        let dir = tempfile::tempdir().unwrap();
+        let peer = |name: &str| {
+            format!("[{name}]\nshared_link = \"https://foo.org/share/{name}\"\nsync_with = []\n")
+        };
+        fs::write(dir.path().join("family.toml"), peer("Family")).unwrap();
+        fs::write(dir.path().join("friends.toml"), peer("Friends")).unwrap();
+        fs::write(dir.path().join("notes.txt"), "not a config").unwrap();
+
+        let config = config::load(&[dir.path().to_path_buf()], None, &Client::new())
+            .await
+            .unwrap();
+        let mut names: Vec<_> = config.keys().collect();
+        names.sort();
+        assert_eq!(names, ["Family", "Friends"]);
+
+        let extra = dir.path().join("extra.yaml");
+        fs::write(
+            &extra,
+            "Friends:\n  shared_link: https://bar.org/share/x\n  sync_with: []\n",
+        )
+        .unwrap();
+        let error = config::load(&[dir.path().to_path_buf()], None, &Client::new())
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("Peer Friends is defined in"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn wildcard_syncs_with_all_other_peers() {
+        let raw = r#"
+            [Hub]
+            shared_link = "https://foo.org/share/hub"
+            sync_with = ["B", "*:both"]
+            exclude = ["C"]
+
+            [A]
+            shared_link = "https://foo.org/share/a"
+            sync_with = []
+
+            [B]
+            shared_link = "https://foo.org/share/b"
+            sync_with = []
+
+            [C]
+            shared_link = "https://foo.org/share/c"
+            sync_with = []
+
+            [D]
+            shared_link = "https://foo.org/share/d"
+            sync_with = []
+        "#;
+        let mut config = config::parse(raw, ConfigFormat::Toml).unwrap();
+        config::expand_wildcards(&mut config).unwrap();
+
+        assert_eq!(config["Hub"].sync_with, ["B", "A:both", "D:both"]);
+
+        config.get_mut("A").unwrap().exclude = vec!["Unknown".to_owned()];
+        assert!(config::expand_wildcards(&mut config).is_err());
+    }
+
+    #[test]
+    fn sync_order_follows_chains() {
+        let raw = r#"
+            [A]
+            shared_link = "https://foo.org/share/a"
+            sync_with = []
+
+            [C]
+            shared_link = "https://foo.org/share/c"
+            sync_with = ["B"]
+
+            [B]
+            shared_link = "https://foo.org/share/b"
+            sync_with = ["A"]
+
+            [E]
+            shared_link = "https://foo.org/share/e"
+            sync_with = []
+
+            [F]
+            shared_link = "https://foo.org/share/f"
+            sync_with = ["E"]
+
+            [G]
+            shared_link = "https://foo.org/share/g"
+            sync_with = ["E:both"]
+
+            [X]
+            shared_link = "https://foo.org/share/x"
+            sync_with = ["Y"]
+
+            [Y]
+            shared_link = "https://foo.org/share/y"
+            sync_with = ["X"]
+        "#;
+        let config = config::parse(raw, ConfigFormat::Toml).unwrap();
+
+        assert_eq!(
+            config::sync_order(&config, false),
+            [
+                ("B", "A"),
+                ("C", "B"),
+                // F receives the assets of G through E
+                ("G", "E:both"),
+                ("F", "E"),
+                ("X", "Y"),
+                ("Y", "X")
+            ]
+        );
+    }
+
+    #[test]
+    fn config_formats_parse_identically() {
+        let toml = r#"
+            [Some_Album]
+            shared_link = "https://immich.example.org/share/key"
+            sync_with = ["Friend:both"]
+
+            [Friend]
+            shared_link = ["https://foo.org/share/first", "https://foo.org/share/second"]
+            sync_with = []
+            concurrency = 2
+        "#;
+        let yaml = r#"
+            Some_Album:
+              shared_link: https://immich.example.org/share/key
+              sync_with: ["Friend:both"]
+            Friend:
+              shared_link:
+                - https://foo.org/share/first
+                - https://foo.org/share/second
+              sync_with: []
+              concurrency: 2
+        "#;
+        let json = r#"{
+            "Some_Album": {
+                "shared_link": "https://immich.example.org/share/key",
+                "sync_with": ["Friend:both"]
+            },
+            "Friend": {
+                "shared_link": ["https://foo.org/share/first", "https://foo.org/share/second"],
+                "sync_with": [],
+                "concurrency": 2
+            }
+        }"#;
+
+        let expected = config::parse(toml, ConfigFormat::Toml).unwrap();
+        assert_eq!(expected.len(), 2);
+        assert_eq!(config::parse(yaml, ConfigFormat::Yaml).unwrap(), expected);
+        assert_eq!(config::parse(json, ConfigFormat::Json).unwrap(), expected);
+    }
+
+    #[test]
+    fn config_format_from_extension() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("a.yml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("a.YAML")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("a.json")),
+            ConfigFormat::Json
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("a.toml")),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config")),
+            ConfigFormat::Toml
+        );
+    }
+
+    #[test]
+    fn filter_skips_archived_and_trashed_assets() {
+        let mut archived = asset_json(2);
+        archived["isArchived"] = true.into();
+        let mut hidden = asset_json(3);
+        hidden["visibility"] = "archive".into();
+        let mut trashed = asset_json(4);
+        trashed["isTrashed"] = true.into();
+        let assets: Vec<Asset> = [asset_json(1), archived, hidden, trashed]
+            .into_iter()
+            .map(|json| serde_json::from_value(json).unwrap())
+            .collect();
+        let matching = |filter: &Filter| -> Vec<_> {
+            assets
+                .iter()
+                .filter(|a| filter.matches(a))
+                .map(|a| a.id.clone())
+                .collect()
+        };
+
+        assert_eq!(matching(&Filter::default()), ["asset-1"]);
+        let filter = Filter {
+            include_archived: true,
+            ..Filter::default()
+        };
+        assert_eq!(matching(&filter), ["asset-1", "asset-2", "asset-3"]);
+    }
+
+    #[test]
+    fn checksum_algorithms_match_immich_encoding() {
+        let checksum = |algorithm: ChecksumAlgorithm| {
+            let mut hasher = algorithm.hasher();
+            hasher.update(b"a");
+            hasher.update(b"bc");
+            hasher.finish()
+        };
+
+        assert_eq!(
+            checksum(ChecksumAlgorithm::Sha1),
+            "qZk+NkcGgWq6PiVxeFDCbJzQ2J0="
+        );
+        assert_eq!(
+            checksum(ChecksumAlgorithm::Sha256),
+            "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0="
+        );
+        assert_eq!(
+            checksum(ChecksumAlgorithm::Blake3),
+            "ZDezrDhGUTP/tjt1JzqNtUjFWEZdedsD/TWcbNW9nYU="
+        );
+    }
+
+    #[test]
+    fn quality_selects_download() {
+        let mut video = asset(2);
+        video.asset_type = AssetType::Video;
+
+        let (path, query) = Quality::Preview.download_path(&asset(1));
+        assert_eq!(path, "assets/asset-1/thumbnail");
+        assert_eq!(query, [("size", "preview")]);
+        let (path, _) = Quality::Thumbnail.download_path(&video);
+        assert_eq!(path, "assets/asset-2/video/playback");
+        let (path, _) = Quality::Original.download_path(&video);
+        assert_eq!(path, "assets/asset-2/original");
+    }
+
+    #[test]
+    fn api_url_uses_api_path() {
+        let url = |path| api_url("https://example.org", &normalize_api_path(path), "albums");
+
+        assert_eq!(url(None), "https://example.org/api/albums");
+        assert_eq!(
+            url(Some("/immich/api/")),
+            "https://example.org/immich/api/albums"
+        );
+        assert_eq!(url(Some("/")), "https://example.org/albums");
+    }
+
+    #[test]
+    fn parse_share_link_splits_base_url_and_key() {
+        let parse = |link| config::parse_share_link(link).unwrap();
+        let expected = ("https://immich.example.org".to_owned(), "key".to_owned());
+
+        assert_eq!(parse("https://immich.example.org/share/key"), expected);
+        assert_eq!(parse("https://immich.example.org/share/key/"), expected);
+        assert_eq!(parse("https://immich.example.org/share/key//"), expected);
+        assert_eq!(parse("https://immich.example.org/share/key/?x=1"), expected);
+        assert_eq!(
+            parse("https://immich.example.org/share/a-b_c.d~e"),
+            (
+                "https://immich.example.org".to_owned(),
+                "a-b_c.d~e".to_owned()
+            )
+        );
+        assert_eq!(
+            parse("https://immich.example.org/share/key?x=1#y"),
+            expected
+        );
+        assert_eq!(
+            parse("https://immich.example.org/share/key/photos/asset-1"),
+            expected
+        );
+        assert_eq!(
+            parse("http://example.org:2283/immich/share/key"),
+            (
+                "http://example.org:2283/immich".to_owned(),
+                "key".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn parse_share_link_rejects_malformed_links() {
+        for link in [
+            "immich.example.org/share/key",
+            "ftp://immich.example.org/share/key",
+            "https://immich.example.org/key",
+            "https://immich.example.org/share/",
+            "https://immich.example.org/share//key",
+            "",
+        ] {
+            assert!(
+                config::parse_share_link(link).is_err(),
+                "{link} was accepted"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_assets_retries_ids_that_were_not_added() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/album/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"id": "existing-1", "success": true},
+                {"id": "existing-2", "success": false, "error": "unknown"},
+                {"id": "existing-3", "success": false, "error": "duplicate"},
+            ])))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/album/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"id": "existing-2", "success": false, "error": "no_permission"},
+            ])))
+            .mount(&server)
+            .await;
+
+        let assets = vec![asset(1), asset(2), asset(3)];
+        let existing = assets
+            .iter()
+            .map(|a| (a.id.clone(), a.id.replace("asset", "existing")))
+            .collect();
+        let options = SyncOptions {
+            retries: 2,
+            continue_on_error: true,
+            ..options()
+        };
+        let mut summary = PairSummary::default();
+        link(&server)
+            .upload_assets(
+                &Client::new(),
+                &assets,
+                &existing,
+                None,
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(summary.uploaded, 2);
+        assert_eq!(summary.failures.len(), 1);
+        let (asset, error) = &summary.failures[0];
+        assert_eq!(asset.id, "asset-2");
+        assert!(error.to_string().contains("no_permission"), "{error}");
+        let requests = server.received_requests().await.unwrap();
+        let bodies: Vec<_> = requests
+            .iter()
+            .filter(|r| r.method == Method::PUT)
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .collect();
+        assert_eq!(
+            bodies,
+            [
+                r#"{"ids":["existing-1","existing-2","existing-3"]}"#,
+                r#"{"ids":["existing-2"]}"#
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_assets_adds_to_album_in_batches() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/album/assets"))
+            .and(body_string_contains("existing-3"))
+            .respond_with(ResponseTemplate::new(500))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/album/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&server)
+            .await;
+
+        let assets = vec![asset(1), asset(2), asset(3)];
+        let existing = assets
+            .iter()
+            .map(|a| (a.id.clone(), a.id.replace("asset", "existing")))
+            .collect();
+        let options = SyncOptions {
+            continue_on_error: true,
+            album_batch_size: 2,
+            ..options()
+        };
+        let mut summary = PairSummary::default();
+        link(&server)
+            .upload_assets(
+                &Client::new(),
+                &assets,
+                &existing,
+                None,
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(summary.uploaded, 2);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(summary.failures[0].0.id, "asset-3");
+        let requests = server.received_requests().await.unwrap();
+        let bodies: Vec<_> = requests
+            .iter()
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .collect();
+        assert_eq!(
+            bodies,
+            [
+                r#"{"ids":["existing-1","existing-2"]}"#,
+                r#"{"ids":["existing-3"]}"#
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn upload_assets_records_added_batches_in_state() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/album/assets"))
+            .and(body_string_contains("existing-3"))
+            .respond_with(ResponseTemplate::new(500))
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/album/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&server)
+            .await;
+
+        let assets = vec![asset(1), asset(2), asset(3)];
+        let existing = assets
+            .iter()
+            .map(|a| (a.id.clone(), a.id.replace("asset", "existing")))
+            .collect();
+        let dir = tempfile::tempdir().unwrap();
+        let state_path = dir.path().join("state.json");
+        let options = SyncOptions {
+            album_batch_size: 2,
+            state: State::load(&state_path).unwrap(),
+            ..options()
+        };
+        let result = link(&server)
+            .upload_assets(
+                &Client::new(),
+                &assets,
+                &existing,
+                None,
+                &options,
+                &mut PairSummary::default(),
+            )
+            .await;
+
+        assert!(result.is_err());
+        // The first batch is recorded although the run failed in the second one
+        let mut remaining = assets.clone();
+        State::load(&state_path)
+            .unwrap()
+            .retain_new("album", &mut remaining);
+        let ids: Vec<_> = remaining.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, ["asset-3"]);
+    }
+
+    #[tokio::test]
+    async fn sync_description_needs_api_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("PATCH"))
+            .and(path("/api/albums/album"))
+            .and(body_string_contains(r#""description":"Holidays 2024""#))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let mut source = link(&server);
+        source.album.description = Some("Holidays 2024".to_owned());
+
+        let mut target = link(&server);
+        target
+            .sync_description(&source, &options(), &Client::new())
+            .await
+            .unwrap();
+        assert_eq!(target.album.description, None);
+
+        target.auth = Auth::ApiKey("api-key".to_owned());
+        target
+            .sync_description(&source, &options(), &Client::new())
+            .await
+            .unwrap();
+        assert_eq!(target.album.description.as_deref(), Some("Holidays 2024"));
+        // Nothing to do once the descriptions are equal
+        target
+            .sync_description(&source, &options(), &Client::new())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn upload_assets_resumes_interrupted_upload() {
+        let server = MockServer::start().await;
+        Mock::given(method("OPTIONS"))
+            .and(path("/api/upload"))
+            .respond_with(
+                ResponseTemplate::new(204)
+                    .insert_header("Tus-Resumable", "1.0.0")
+                    .insert_header("Tus-Version", "1.0.0")
+                    .insert_header("Tus-Extension", "creation, termination"),
+            )
+            .mount(&server)
+            .await;
+        // The connection drops before the upload is created
+        Mock::given(method("POST"))
+            .and(path("/api/upload"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/upload"))
+            .respond_with(ResponseTemplate::new(201).insert_header("Location", "/api/upload/abc"))
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/api/upload/abc"))
+            .respond_with(ResponseTemplate::new(502))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("HEAD"))
+            .and(path("/api/upload/abc"))
+            .respond_with(ResponseTemplate::new(200).insert_header("Upload-Offset", "0"))
+            .mount(&server)
+            .await;
+        // As the tus protocol specifies, the completed upload has no body
+        Mock::given(method("PATCH"))
+            .and(path("/api/upload/abc"))
+            .respond_with(ResponseTemplate::new(204).insert_header("Upload-Offset", "18"))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/assets/bulk-upload-check"))
+            .and(body_string_contains("checksum-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [{
+                    "id": "asset-1",
+                    "action": "reject",
+                    "reason": "duplicate",
+                    "assetId": "uploaded-1",
+                }],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/album/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&server)
+            .await;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "content of asset-1").unwrap();
+        let mut asset = asset(1);
+        asset.path = Some(file.path().to_path_buf());
+        let options = SyncOptions {
+            retries: 2,
+            resumable_min_size: Some(0),
+            ..options()
+        };
+        let mut summary = PairSummary::default();
+        link(&server)
+            .upload_assets(
+                &Client::new(),
+                &[asset],
+                &HashMap::new(),
+                None,
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(summary.uploaded, 1);
+        let requests = server.received_requests().await.unwrap();
+        let added = requests.iter().find(|r| r.method == Method::PUT).unwrap();
+        assert_eq!(added.body, br#"{"ids":["uploaded-1"]}"#);
+        let patches: Vec<_> = requests
+            .iter()
+            .filter(|r| r.method == Method::PATCH)
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .collect();
+        assert_eq!(patches, ["content of asset-1", "content of asset-1"]);
+        let created = requests.iter().find(|r| r.method == Method::POST).unwrap();
+        let metadata = created.headers.get("Upload-Metadata").unwrap();
+        assert!(
+            metadata
+                .to_str()
+                .unwrap()
+                .starts_with(&format!("filename {}", BASE64.encode("IMG_1.jpg"))),
+            "{metadata:?}"
+        );
+        assert!(requests.iter().all(|r| r.url.path() != "/api/assets"));
+    }
+
+    #[tokio::test]
+    async fn upload_assets_without_adding_to_album() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/assets"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": "uploaded-1",
+            })))
+            .mount(&server)
+            .await;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "content of asset-1").unwrap();
+        let mut asset = asset(1);
+        asset.path = Some(file.path().to_path_buf());
+        let options = SyncOptions {
+            add_to_album: false,
+            ..options()
+        };
+        let mut summary = PairSummary::default();
+        link(&server)
+            .upload_assets(
+                &Client::new(),
+                &[asset],
+                &HashMap::new(),
+                None,
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(summary.uploaded, 1);
+        let requests = server.received_requests().await.unwrap();
+        assert!(requests.iter().all(|r| r.method == Method::POST));
+    }
+
+    #[tokio::test]
+    async fn upload_assets_uses_existing_asset_after_retry() {
+        let server = MockServer::start().await;
+        // The first attempt is stored by the server, but the response is lost
+        Mock::given(method("POST"))
+            .and(path("/api/assets"))
+            .respond_with(ResponseTemplate::new(502))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "existing-1",
+                "status": "duplicate",
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/album/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&server)
+            .await;
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        fs::write(file.path(), "content of asset-1").unwrap();
+        let mut asset = asset(1);
+        asset.path = Some(file.path().to_path_buf());
+        let options = SyncOptions {
+            retries: 2,
+            ..options()
+        };
+        let mut summary = PairSummary::default();
+        link(&server)
+            .upload_assets(
+                &Client::new(),
+                &[asset],
+                &HashMap::new(),
+                None,
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(summary.uploaded, 1);
+        assert!(summary.failures.is_empty());
+        let requests = server.received_requests().await.unwrap();
+        let uploads = requests.iter().filter(|r| r.method == Method::POST).count();
+        assert_eq!(uploads, 2);
+        let added: Vec<_> = requests
+            .iter()
+            .filter(|r| r.method == Method::PUT)
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .collect();
+        assert_eq!(added, [r#"{"ids":["existing-1"]}"#]);
+    }
+
+    #[tokio::test]
+    async fn upload_missing_uploads_assets_of_overlapping_sources_once() {
+        let sources = MockServer::start().await;
+        for id in 1..=3 {
+            Mock::given(method("GET"))
+                .and(path(format!("/api/assets/asset-{id}/original")))
+                .respond_with(ResponseTemplate::new(200).set_body_raw("content", "image/jpeg"))
+                .mount(&sources)
+                .await;
+        }
+        let server = MockServer::start().await;
+        // The listing of the target doesn't include the uploaded assets yet
+        Mock::given(method("GET"))
+            .and(path("/api/albums/target"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "assets": [],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/assets/bulk-upload-check"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/assets"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": "new",
+            })))
+            .expect(3)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/target/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&server)
+            .await;
+
+        let mut target = link(&server);
+        target.album.id = "target".to_owned();
+        let mut alice = link(&sources);
+        alice.album.assets = vec![asset(1), asset(2)];
+        let mut bob = link(&sources);
+        bob.album.assets = vec![asset(2), asset(3)];
+        let options = SyncOptions {
+            verify: false,
+            ..options()
+        };
+        let client = Client::new();
+
+        for source in [&alice, &bob] {
+            let dir = tempfile::tempdir().unwrap();
+            target
+                .upload_missing(source, &options, None, &client, dir.path())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn upload_missing_downloads_piped_assets_to_verify_them() {
+        let sources = MockServer::start().await;
+        let body = "content of asset-1";
+        Mock::given(method("GET"))
+            .and(path("/api/assets/asset-1/original"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body, "image/jpeg"))
+            .mount(&sources)
+            .await;
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/albums/target"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "assets": [],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/assets/bulk-upload-check"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/assets"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": "new",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/target/assets"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&server)
+            .await;
+
+        let mut target = link(&server);
+        target.album.id = "target".to_owned();
+        let mut source = link(&sources);
+        source.album.assets = vec![asset(1)];
+        source.album.assets[0].checksum = BASE64.encode(Sha1::digest(body));
+        let options = SyncOptions {
+            pipe: true,
+            ..options()
+        };
+        let dir = tempfile::tempdir().unwrap();
+
+        let summary = target
+            .upload_missing(&source, &options, None, &Client::new(), dir.path())
+            .await
+            .unwrap();
+
+        assert_eq!(summary.uploaded, 1);
+        assert_eq!(summary.downloaded, 1);
+        let downloaded = dir.path().join("IMG_1.jpg");
+        assert_eq!(fs::read_to_string(downloaded).unwrap(), body);
+    }
+
+    #[tokio::test]
+    async fn upload_missing_adds_assets_of_same_instance_directly() {
+        let server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/target/assets"))
+            .and(body_string_contains("asset-1"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"id": "asset-1", "success": true},
+                {"id": "asset-2", "success": false, "error": "no_permission"},
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/api/albums/target/assets"))
+            .and(body_string_contains("uploaded-2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .mount(&server)
+            .await;
+        // Only the asset that can't be added directly is downloaded and uploaded again
+        Mock::given(method("GET"))
+            .and(path("/api/assets/asset-2/original"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("content", "image/jpeg"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/assets/bulk-upload-check"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [],
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/assets"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": "uploaded-2",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/albums/target"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "assets": [asset_json(1), asset_json(2)],
+            })))
+            .mount(&server)
+            .await;
+
+        let mut target = link(&server);
+        target.album.id = "target".to_owned();
+        let mut source = link(&server);
+        source.album.assets = vec![asset(1), asset(2)];
+        let options = SyncOptions {
+            verify: false,
+            ..options()
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let summary = target
+            .upload_missing(&source, &options, None, &Client::new(), dir.path())
+            .await
+            .unwrap();
+
+        assert_eq!(summary.uploaded, 2);
+        assert_eq!(summary.downloaded, 1);
+    }
+
+    #[tokio::test]
+    async fn download_retries_after_rejected_share_link() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/assets/asset-1/original"))
+            .respond_with(ResponseTemplate::new(401))
+            .up_to_n_times(1)
+            .with_priority(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/assets/asset-1/original"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("content", "image/jpeg"))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/shared-links/me"))
+            .and(query_param("key", "key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "key": "key",
+            })))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = SyncOptions {
+            verify: false,
+            ..options()
+        };
+        let mut assets = vec![asset(1)];
+        let mut summary = PairSummary::default();
+        link(&server)
+            .download_assets(
+                &mut assets,
+                &Client::new(),
+                dir.path(),
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap();
+        assert_eq!(summary.downloaded, 1);
+
+        // The link has expired for good now
+        Mock::given(method("GET"))
+            .and(path("/api/assets/asset-2/original"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        let mut assets = vec![asset(2)];
+        let error = link(&server)
+            .download_assets(
+                &mut assets,
+                &Client::new(),
+                dir.path(),
+                &options,
+                &mut summary,
+            )
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error.downcast_ref(), Some(IssError::DeadLink { .. })),
+            "{error:#}"
+        );
+    }
+
+    #[tokio::test]
+    async fn download_assets_rejects_html_pages() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/assets/asset-1/original"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("<html>", "text/html"))
+            .mount(&server)
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut assets = vec![asset(1)];
+        let mut summary = PairSummary::default();
+        let options = SyncOptions {
+            verify: false,
+            ..options()
+        };
+        let result = link(&server)
+            .download_assets(
+                &mut assets,
+                &Client::new(),
+                dir.path(),
+                &options,
+                &mut summary,
+            )
+            .await;
+
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("text/html"));
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(IssError::NotAFile { content_type, .. }) if content_type == "text/html"
+        ));
+    }
 }
@@ -0,0 +1,110 @@
+use crate::summary::PairSummary;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// Metric of a pair of albums, with its name and help text
+type PairMetric = (&'static str, &'static str, fn(&PairSummary) -> u64);
+
+/// Metrics of every pair
+const PAIR_METRICS: [PairMetric; 6] = [
+    (
+        "iss_assets_synced",
+        "Number of assets added to the target album in the last run",
+        |pair| pair.uploaded as u64,
+    ),
+    (
+        "iss_assets_downloaded",
+        "Number of assets downloaded from the source album in the last run",
+        |pair| pair.downloaded as u64,
+    ),
+    (
+        "iss_assets_reused",
+        "Number of assets whose download for another album was reused in the last run",
+        |pair| pair.reused as u64,
+    ),
+    (
+        "iss_assets_skipped",
+        "Number of assets of the source album that were not synced in the last run",
+        |pair| pair.skipped as u64,
+    ),
+    (
+        "iss_assets_failed",
+        "Number of assets that failed to sync in the last run",
+        |pair| pair.failed as u64,
+    ),
+    (
+        "iss_bytes_downloaded",
+        "Number of bytes downloaded from the source album in the last run",
+        |pair| pair.bytes,
+    ),
+];
+
+/// Write the metrics of a run to the file in the Prometheus text format, for the textfile
+/// collector of the node exporter. The file is replaced atomically, so it is never read while
+/// only partially written.
+pub fn write(
+    path: &Path,
+    started: DateTime<Utc>,
+    elapsed: Duration,
+    success: bool,
+    pairs: &[PairSummary],
+) -> Result<()> {
+    let mut out = String::new();
+    gauge(
+        &mut out,
+        "iss_last_run_timestamp_seconds",
+        "Time the last run started",
+        started.timestamp(),
+    );
+    gauge(
+        &mut out,
+        "iss_last_run_duration_seconds",
+        "Duration of the last run",
+        elapsed.as_secs_f64(),
+    );
+    gauge(
+        &mut out,
+        "iss_last_run_success",
+        "Whether the last run synced all assets",
+        u8::from(success),
+    );
+    for (name, help, value) in PAIR_METRICS {
+        writeln!(out, "# HELP {name} {help}").unwrap();
+        writeln!(out, "# TYPE {name} gauge").unwrap();
+        for pair in pairs {
+            writeln!(
+                out,
+                "{name}{{from=\"{}\",from_album=\"{}\",to=\"{}\",to_album=\"{}\"}} {}",
+                escape(&pair.from),
+                escape(&pair.from_album),
+                escape(&pair.to),
+                escape(&pair.to_album),
+                value(pair)
+            )
+            .unwrap();
+        }
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, out)?;
+    fs::rename(&tmp_path, path).with_context(|| format!("Writing metrics {}", path.display()))
+}
+
+/// Append a gauge without labels
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    writeln!(out, "# HELP {name} {help}").unwrap();
+    writeln!(out, "# TYPE {name} gauge").unwrap();
+    writeln!(out, "{name} {value}").unwrap();
+}
+
+/// Escape a label value
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,92 @@
+use crate::summary::PairSummary;
+use anyhow::{Result, bail};
+use reqwest::{Client, Url};
+use serde::Serialize;
+use tracing::{debug, warn};
+
+/// Payload posted to generic webhooks
+#[derive(Serialize, Debug)]
+struct Notification<'a> {
+    /// "success" or "failure"
+    status: &'static str,
+
+    dry_run: bool,
+
+    /// Number of assets added to target albums
+    synced: usize,
+
+    /// Number of assets that failed to sync
+    failed: usize,
+
+    /// Number of bytes downloaded over all pairs
+    bytes: u64,
+
+    /// Reason why the run failed, only set on failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// Notify the webhook about the result of a run. Failures to notify are only logged, so they
+/// don't affect the result of the run.
+pub async fn send(
+    client: &Client,
+    url: &str,
+    dry_run: bool,
+    pairs: &[PairSummary],
+    result: &Result<()>,
+) {
+    let error = result.as_ref().err().map(|e| format!("{e:#}"));
+    let notification = Notification {
+        status: if error.is_none() {
+            "success"
+        } else {
+            "failure"
+        },
+        dry_run,
+        synced: pairs.iter().map(|pair| pair.uploaded).sum(),
+        failed: pairs.iter().map(|pair| pair.failed).sum(),
+        bytes: pairs.iter().map(|pair| pair.bytes).sum(),
+        error: error.as_deref(),
+    };
+    match post(client, url, &notification).await {
+        Ok(()) => debug!("Sent notification to {url}"),
+        Err(e) => warn!("Sending notification to {url} failed: {e:#}"),
+    }
+}
+
+/// Post the notification, as message with headers for ntfy and as JSON otherwise
+async fn post(client: &Client, url: &str, notification: &Notification<'_>) -> Result<()> {
+    let parsed = Url::parse(url)?;
+    let req = if is_ntfy(&parsed) {
+        let (title, priority, tags) = match notification.error {
+            None => ("iss sync finished", "default", "white_check_mark"),
+            Some(_) => ("iss sync failed", "high", "warning"),
+        };
+        let mut message = format!(
+            "{} assets synced, {} failed",
+            notification.synced, notification.failed
+        );
+        if let Some(error) = notification.error {
+            message.push_str(&format!("\n{error}"));
+        }
+        client
+            .post(parsed)
+            .header("Title", title)
+            .header("Priority", priority)
+            .header("Tags", tags)
+            .body(message)
+    } else {
+        client.post(parsed).json(notification)
+    };
+    let res = req.send().await?;
+    if !res.status().is_success() {
+        bail!("{}", res.status());
+    }
+    Ok(())
+}
+
+/// Whether the URL is a topic on ntfy.sh or a self-hosted ntfy server (ntfy.example.org)
+fn is_ntfy(url: &Url) -> bool {
+    url.host_str()
+        .is_some_and(|host| host == "ntfy.sh" || host.starts_with("ntfy."))
+}
@@ -0,0 +1,101 @@
+use crate::config::Config;
+use crate::summary::PairSummary;
+use crate::{Asset, SharedLink};
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+/// The assets a dry run would sync, written with --plan-out and synced with --apply-plan
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Plan {
+    /// Time the plan was made
+    pub created: DateTime<Utc>,
+
+    pub pairs: Vec<PlannedPair>,
+}
+
+/// The assets that would be added from one album to another
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlannedPair {
+    /// Name of the peer the assets are taken from
+    pub from: String,
+
+    /// Id of the album the assets are taken from
+    pub from_album: String,
+
+    /// Name of the peer the assets are added to
+    pub to: String,
+
+    /// Id of the album the assets are added to
+    pub to_album: String,
+
+    pub assets: Vec<PlannedAsset>,
+}
+
+/// An asset that would be synced, identified by its id in the source album and its checksum, so
+/// a changed asset is not synced
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlannedAsset {
+    pub id: String,
+    pub checksum: String,
+
+    /// File name of the asset, only for reviewing the plan
+    pub file_name: String,
+}
+
+impl PlannedPair {
+    pub fn new(from: &SharedLink, to: &SharedLink, assets: &[Asset]) -> Self {
+        Self {
+            from: from.peer.clone(),
+            from_album: from.album.id.clone(),
+            to: to.peer.clone(),
+            to_album: to.album.id.clone(),
+            assets: assets
+                .iter()
+                .map(|asset| PlannedAsset {
+                    id: asset.id.clone(),
+                    checksum: asset.checksum.clone(),
+                    file_name: asset.file_name.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Plan {
+    /// The plan of a dry run with the given results
+    pub fn new(pairs: &[PairSummary]) -> Self {
+        Self {
+            created: Utc::now(),
+            pairs: pairs.iter().filter_map(|pair| pair.plan.clone()).collect(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw =
+            fs::read_to_string(path).with_context(|| format!("Reading plan {}", path.display()))?;
+        serde_json::from_str(&raw).with_context(|| format!("Invalid plan {}", path.display()))
+    }
+
+    /// Check that the plan only syncs between different peers of the config
+    pub fn validate(&self, config: &Config) -> Result<()> {
+        for pair in &self.pairs {
+            for peer in [&pair.from, &pair.to] {
+                if !config.contains_key(peer) {
+                    bail!("The plan syncs peer {peer}, which is not in the config");
+                }
+            }
+            if pair.from == pair.to {
+                bail!("The plan syncs peer {} with itself", pair.from);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Writing plan {}", path.display()))
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{Asset, stats};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::LazyLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// All progress bars are drawn through this, so log output can hide them while it is written
+static BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+/// Writer for log output that hides the progress bars while writing, so they don't get garbled
+pub struct LogWriter;
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        BARS.suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+/// Never draw the progress bars, the transfers are still counted for `--stats`
+pub fn hide() {
+    BARS.set_draw_target(ProgressDrawTarget::hidden());
+}
+
+/// Hide the progress bars while running `f`, e.g. to ask a question on the terminal
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    BARS.suspend(f)
+}
+
+/// Direction of a transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    Download,
+    Upload,
+}
+
+/// Receives the progress of the downloads and uploads, e.g. to render it or to collect metrics
+pub trait Progress: Send + Sync {
+    /// Start a batch of `len` transfers of one pair of albums. The batch ends when the returned
+    /// value is dropped.
+    fn batch(&self, transfer: Transfer, len: usize) -> Box<dyn BatchProgress>;
+}
+
+impl fmt::Debug for dyn Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Progress")
+    }
+}
+
+/// Receives the progress of the transfers of one batch, which may run in parallel
+pub trait BatchProgress: Send + Sync {
+    /// Another `bytes` bytes were transferred. Downloads report every received chunk, uploads
+    /// report each file once it has been sent.
+    fn bytes(&self, _bytes: u64) {}
+
+    /// The transfer of the asset, including the video of a live photo, finished after
+    /// `duration` with `size` bytes in total
+    fn done(&self, _asset: &Asset, _size: u64, _duration: Duration) {}
+
+    /// The transfer of the asset failed
+    fn failed(&self, _asset: &Asset, _error: &anyhow::Error) {}
+}
+
+/// Progress bars on the terminal, which also count the transfers for `--stats`
+pub struct Terminal;
+
+impl Progress for Terminal {
+    fn batch(&self, transfer: Transfer, len: usize) -> Box<dyn BatchProgress> {
+        Box::new(TransferProgress::new(transfer, len))
+    }
+}
+
+/// Progress bar for a batch of downloads or uploads, counting assets and transferred bytes.
+/// The bar is cleared when it is dropped, so an early return on error leaves a clean terminal.
+struct TransferProgress {
+    transfer: Transfer,
+    bar: ProgressBar,
+    bytes: AtomicU64,
+}
+
+impl TransferProgress {
+    /// Create a progress bar for `len` assets
+    fn new(transfer: Transfer, len: usize) -> Self {
+        let verb = match transfer {
+            Transfer::Download => "Downloading",
+            Transfer::Upload => "Uploading",
+        };
+        let style = ProgressStyle::with_template("{prefix} [{bar:30}] {pos}/{len} {msg}")
+            .expect("progress bar template is valid")
+            .progress_chars("=> ");
+        let bar = ProgressBar::new(len as u64)
+            .with_style(style)
+            .with_prefix(verb);
+        Self {
+            transfer,
+            bar: BARS.add(bar),
+            bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+impl BatchProgress for TransferProgress {
+    fn bytes(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn done(&self, asset: &Asset, size: u64, duration: Duration) {
+        let total = self.bytes.load(Ordering::Relaxed);
+        self.bar
+            .set_message(format!("{} - {}", HumanBytes(total), asset.file_name));
+        self.bar.inc(1);
+
+        let verb = match self.transfer {
+            Transfer::Download => {
+                stats::downloaded(size);
+                "download"
+            }
+            Transfer::Upload => {
+                stats::uploaded(size);
+                "upload"
+            }
+        };
+        stats::transfer(|| format!("{verb} of {}", asset.file_name), duration);
+    }
+}
+
+impl Drop for TransferProgress {
+    fn drop(&mut self) {
+        self.bar.finish_and_clear();
+        BARS.remove(&self.bar);
+    }
+}
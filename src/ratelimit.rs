@@ -0,0 +1,68 @@
+use reqwest::{RequestBuilder, Response};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Limiter for all outgoing requests, only set if --rate-limit is given
+static LIMITER: OnceLock<TokenBucket> = OnceLock::new();
+
+/// Token bucket that allows `rate` requests per second on average, with bursts of up to one
+/// second worth of requests
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    state: Mutex<Bucket>,
+}
+
+struct Bucket {
+    /// Available tokens, negative if requests are already waiting for tokens
+    tokens: f64,
+
+    /// Time the tokens were last refilled
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        let burst = rate.max(1.0);
+        Self {
+            rate,
+            burst,
+            state: Mutex::new(Bucket {
+                tokens: burst,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token, waiting until one is available
+    async fn acquire(&self) {
+        let wait = {
+            let mut bucket = self.state.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst) - 1.0;
+            bucket.refilled = now;
+            if bucket.tokens >= 0.0 {
+                return;
+            }
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        };
+        tokio::time::sleep(wait).await;
+    }
+}
+
+/// Limit all requests sent with [`send`] to `rate` requests per second
+pub fn init(rate: f64) {
+    if LIMITER.set(TokenBucket::new(rate)).is_err() {
+        panic!("rate limit is already set");
+    }
+}
+
+/// Send the request as soon as the rate limit allows it
+pub async fn send(request: RequestBuilder) -> reqwest::Result<Response> {
+    if let Some(limiter) = LIMITER.get() {
+        limiter.acquire().await;
+    }
+    crate::stats::request();
+    request.send().await
+}
@@ -0,0 +1,144 @@
+use crate::summary::PairSummary;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// Record of a single run, appended to the file given with --report
+#[derive(Serialize, Debug)]
+pub struct RunReport {
+    /// Time the run started
+    started: DateTime<Utc>,
+
+    /// Duration of the run in seconds
+    elapsed: f64,
+
+    dry_run: bool,
+
+    /// Reason why the run failed or was aborted, only set on failures
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+
+    pairs: Vec<PairReport>,
+}
+
+/// Record of syncing one album to another
+#[derive(Serialize, Debug)]
+struct PairReport {
+    from: String,
+    from_album: String,
+    to: String,
+    to_album: String,
+    synced: Vec<AssetReport>,
+    failed: Vec<AssetReport>,
+}
+
+/// Record of a single synced or failed asset
+#[derive(Serialize, Debug)]
+struct AssetReport {
+    file: String,
+    checksum: String,
+
+    /// Reason of the failure, only set for failed assets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl RunReport {
+    pub fn new(
+        started: DateTime<Utc>,
+        elapsed: Duration,
+        dry_run: bool,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            started,
+            elapsed: elapsed.as_secs_f64(),
+            dry_run,
+            error,
+            pairs: Vec::new(),
+        }
+    }
+
+    /// Append the report of the run with the given results to the file, as JSON line if the file
+    /// name ends with .jsonl or .json and as text otherwise
+    pub fn append(mut self, path: &Path, pairs: &[PairSummary]) -> Result<()> {
+        self.pairs = pairs.iter().map(PairReport::from).collect();
+        let json = path.extension().is_some_and(|ext| {
+            ext.eq_ignore_ascii_case("jsonl") || ext.eq_ignore_ascii_case("json")
+        });
+        let report = if json {
+            serde_json::to_string(&self)? + "\n"
+        } else {
+            self.to_text()
+        };
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(report.as_bytes())?;
+        Ok(())
+    }
+
+    /// Human readable form of the report
+    fn to_text(&self) -> String {
+        let mut text = format!(
+            "Run started {}, took {:.1}s{}\n",
+            self.started.to_rfc3339(),
+            self.elapsed,
+            if self.dry_run { " (dry run)" } else { "" }
+        );
+        if let Some(error) = &self.error {
+            text += &format!("  failed: {error}\n");
+        }
+        for pair in &self.pairs {
+            text += &format!(
+                "  {} ({}) -> {} ({}): {} synced, {} failed\n",
+                pair.from,
+                pair.from_album,
+                pair.to,
+                pair.to_album,
+                pair.synced.len(),
+                pair.failed.len()
+            );
+            for asset in &pair.synced {
+                text += &format!("    synced {} ({})\n", asset.file, asset.checksum);
+            }
+            for asset in &pair.failed {
+                let error = asset.error.as_deref().unwrap_or_default();
+                text += &format!("    failed {} ({}): {error}\n", asset.file, asset.checksum);
+            }
+        }
+        text
+    }
+}
+
+impl From<&PairSummary> for PairReport {
+    fn from(summary: &PairSummary) -> Self {
+        Self {
+            from: summary.from.clone(),
+            from_album: summary.from_album.clone(),
+            to: summary.to.clone(),
+            to_album: summary.to_album.clone(),
+            synced: summary
+                .synced
+                .iter()
+                .map(|asset| AssetReport {
+                    file: asset.file_name.clone(),
+                    checksum: asset.checksum.clone(),
+                    error: None,
+                })
+                .collect(),
+            failed: summary
+                .failures
+                .iter()
+                .map(|(asset, error)| AssetReport {
+                    file: asset.file_name.clone(),
+                    checksum: asset.checksum.clone(),
+                    error: Some(format!("{error:#}")),
+                })
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+use std::future::Future;
+use std::time::Duration;
+use tracing::warn;
+
+/// Delay before the first retry, doubled for every further attempt
+const BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Upper bound for the delay between two attempts
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Upper bound for the delay requested by a server with a Retry-After header
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(600);
+
+/// Number of times a request is retried after a 429 response with a Retry-After header. These
+/// retries don't count towards the attempts, since the server told us when to come back.
+const MAX_RATE_LIMITED: u32 = 20;
+
+/// Whether a response with this status is worth another attempt
+pub fn is_retryable_status(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// Whether an error is a transient network error that is worth another attempt
+fn is_retryable_error(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.is_timeout() || e.is_connect() || e.is_request() || e.is_body())
+}
+
+/// Mark timeouts in the error, so they can be told apart from other failures
+pub fn describe_timeout(error: anyhow::Error) -> anyhow::Error {
+    let timed_out = error
+        .downcast_ref::<reqwest::Error>()
+        .is_some_and(|e| e.is_timeout());
+    if timed_out {
+        error.context("Timed out waiting for the server (see --timeout)")
+    } else {
+        error
+    }
+}
+
+/// The delay requested by the Retry-After header of the response, given either in seconds or as
+/// HTTP date
+fn retry_after(res: &Response) -> Option<Duration> {
+    let value = res.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let time = DateTime::parse_from_rfc2822(value).ok()?;
+            (time.to_utc() - Utc::now()).to_std().unwrap_or_default()
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// Exponential backoff with full jitter for the given (1-based) attempt
+pub fn backoff(attempt: u32) -> Duration {
+    let exp = BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt - 1));
+    let max = exp.min(MAX_DELAY).as_millis() as u64;
+    Duration::from_millis(rand::random_range(max / 2..=max))
+}
+
+/// Run the request produced by `send` up to `attempts` times until it yields a response that
+/// is not a transient failure. Network errors, 5xx and 429 responses are retried, everything
+/// else is returned to the caller as is. 429 responses with a Retry-After header are retried
+/// after the requested delay. `what` describes the request in the log output.
+pub async fn with_retry<F, Fut>(attempts: u32, what: &str, mut send: F) -> Result<Response>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<Response>>,
+{
+    let mut attempt = 1;
+    let mut rate_limited = 0;
+    loop {
+        let result = send().await;
+        if let Ok(res) = &result
+            && res.status() == StatusCode::TOO_MANY_REQUESTS
+            && rate_limited < MAX_RATE_LIMITED
+            && let Some(delay) = retry_after(res)
+        {
+            rate_limited += 1;
+            warn!(
+                "{what} was rate limited, retrying in {:.1}s as requested by the server",
+                delay.as_secs_f32()
+            );
+            tokio::time::sleep(delay).await;
+            continue;
+        }
+
+        let reason = match result {
+            Ok(res) if attempt < attempts && is_retryable_status(res.status()) => {
+                res.status().to_string()
+            }
+            Err(e) if attempt < attempts && is_retryable_error(&e) => e.to_string(),
+            result => return result.map_err(describe_timeout),
+        };
+
+        let delay = backoff(attempt);
+        attempt += 1;
+        warn!(
+            "{what} failed ({reason}), retrying in {:.1}s (attempt {attempt}/{attempts})",
+            delay.as_secs_f32()
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
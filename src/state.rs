@@ -0,0 +1,80 @@
+use crate::Asset;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Checksums of the assets that have been added to each album during the current run. With
+/// --state they are also written to a file after every batch, so an interrupted run can be
+/// resumed. The record is cleared once a run completes without failures.
+#[derive(Debug, Default)]
+pub struct State {
+    albums: Mutex<Albums>,
+
+    /// The file this state is stored in
+    path: Option<PathBuf>,
+}
+
+/// Added checksums keyed by the id of the target album
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Albums {
+    albums: HashMap<String, HashSet<String>>,
+}
+
+impl State {
+    /// Load the state of an interrupted run from the given file. A missing file results in an
+    /// empty state.
+    pub fn load(path: &Path) -> Result<Self> {
+        let albums = match fs::read_to_string(path) {
+            Ok(raw) => serde_json::from_str(&raw)
+                .with_context(|| format!("Invalid state file {}", path.display()))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Albums::default(),
+            Err(e) => return Err(e).context(format!("Reading state {}", path.display())),
+        };
+        Ok(Self {
+            albums: Mutex::new(albums),
+            path: Some(path.to_path_buf()),
+        })
+    }
+
+    /// Remove the assets that have already been added to the album from the list
+    pub fn retain_new(&self, album_id: &str, assets: &mut Vec<Asset>) {
+        if let Some(checksums) = self.albums.lock().unwrap().albums.get(album_id) {
+            assets.retain(|asset| !checksums.contains(&asset.checksum));
+        }
+    }
+
+    /// Record that the assets with the given checksums have been added to the album
+    pub fn insert<'a>(
+        &self,
+        album_id: &str,
+        checksums: impl IntoIterator<Item = &'a str>,
+    ) -> Result<()> {
+        let mut albums = self.albums.lock().unwrap();
+        let entries = albums.albums.entry(album_id.to_owned()).or_default();
+        entries.extend(checksums.into_iter().map(str::to_owned));
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string(&*albums)?)?;
+        fs::rename(&tmp_path, path).with_context(|| format!("Writing state {}", path.display()))
+    }
+
+    /// Forget all added assets after a run has completed and remove the state file
+    pub fn finish(&self) -> Result<()> {
+        self.albums.lock().unwrap().albums.clear();
+        match &self.path {
+            Some(path) => match fs::remove_file(path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => {
+                    Err(e).context(format!("Removing state {}", path.display()))
+                }
+                _ => Ok(()),
+            },
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use indicatif::{HumanBytes, HumanDuration};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Metrics of the current run, printed at its end with --stats
+static STATS: Stats = Stats {
+    requests: AtomicU64::new(0),
+    downloaded: AtomicU64::new(0),
+    uploaded: AtomicU64::new(0),
+    slowest: Mutex::new(None),
+};
+
+struct Stats {
+    /// Number of HTTP requests sent
+    requests: AtomicU64,
+
+    /// Number of bytes downloaded
+    downloaded: AtomicU64,
+
+    /// Number of bytes uploaded
+    uploaded: AtomicU64,
+
+    /// Duration and description of the slowest download or upload
+    slowest: Mutex<Option<(Duration, String)>>,
+}
+
+/// Count a sent request
+pub fn request() {
+    STATS.requests.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count a finished download of the given size
+pub fn downloaded(bytes: u64) {
+    STATS.downloaded.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Count a finished upload of the given size
+pub fn uploaded(bytes: u64) {
+    STATS.uploaded.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// Record the duration of a download or upload, described by `what`
+pub fn transfer(what: impl FnOnce() -> String, duration: Duration) {
+    let mut slowest = STATS.slowest.lock().unwrap();
+    if slowest.as_ref().is_none_or(|(max, _)| duration > *max) {
+        *slowest = Some((duration, what()));
+    }
+}
+
+/// Take the metrics collected since the last call as text, for a run that took `wall_time`
+pub fn take(wall_time: Duration) -> String {
+    let requests = STATS.requests.swap(0, Ordering::Relaxed);
+    let downloaded = STATS.downloaded.swap(0, Ordering::Relaxed);
+    let uploaded = STATS.uploaded.swap(0, Ordering::Relaxed);
+    let slowest = STATS.slowest.lock().unwrap().take();
+
+    let seconds = wall_time.as_secs_f64().max(f64::EPSILON);
+    let throughput = ((downloaded + uploaded) as f64 / seconds) as u64;
+    let mut text = format!(
+        "Requests: {requests}\n\
+         Downloaded: {}\n\
+         Uploaded: {}\n\
+         Wall time: {}\n\
+         Average throughput: {}/s\n",
+        HumanBytes(downloaded),
+        HumanBytes(uploaded),
+        HumanDuration(wall_time),
+        HumanBytes(throughput)
+    );
+    if let Some((duration, what)) = slowest {
+        text.push_str(&format!(
+            "Slowest transfer: {what} ({:.1}s)\n",
+            duration.as_secs_f64()
+        ));
+    }
+    text
+}
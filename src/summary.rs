@@ -0,0 +1,190 @@
+use crate::plan::PlannedPair;
+use crate::{Asset, Failure};
+use clap::ValueEnum;
+use indicatif::HumanBytes;
+use serde::Serialize;
+
+/// Format of the output at the end of a run
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// Human readable log messages
+    #[default]
+    Text,
+
+    /// A JSON object on stdout describing the result of every synced pair of albums
+    Json,
+}
+
+/// Result of syncing the assets of one album to another album
+#[derive(Serialize, Debug, Default)]
+pub struct PairSummary {
+    /// Name of the peer the assets are taken from
+    pub from: String,
+
+    /// Name of the album the assets are taken from
+    pub from_album: String,
+
+    /// Name of the peer the assets are added to
+    pub to: String,
+
+    /// Name of the album the assets are added to
+    pub to_album: String,
+
+    /// Number of assets in the source album
+    pub considered: usize,
+
+    /// Number of assets that were downloaded from the source
+    pub downloaded: usize,
+
+    /// Number of assets whose file was not downloaded again, since it was already downloaded for
+    /// another album during the run
+    pub reused: usize,
+
+    /// Number of assets that were added to the target album, including assets that already
+    /// existed on the target instance
+    pub uploaded: usize,
+
+    /// Number of assets that were not synced because they already are in the target album,
+    /// don't match the filter or are already synced according to the cache
+    pub skipped: usize,
+
+    /// Number of assets that failed to sync
+    pub failed: usize,
+
+    /// Number of missing assets that were left for the next run because of `--limit`
+    pub deferred: usize,
+
+    /// Number of assets that were removed from the target album because they are not in the
+    /// source album, only done with `--mirror`
+    pub removed: usize,
+
+    /// Number of bytes downloaded from the source
+    pub bytes: u64,
+
+    /// File names of the assets that would be synced, only set on dry runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub would_sync: Option<Vec<String>>,
+
+    /// Total size of the assets that would be synced as far as known, only set on dry runs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub would_sync_bytes: Option<u64>,
+
+    /// File names of the assets that would be removed, only set on dry runs with `--mirror`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub would_remove: Option<Vec<String>>,
+
+    /// Assets that were skipped because they already are in the target album, only set with
+    /// `--report-skipped`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub already_present: Option<Vec<SkippedAsset>>,
+
+    /// The assets that would be synced, for the plan of a dry run
+    #[serde(skip)]
+    pub plan: Option<PlannedPair>,
+
+    /// The assets that were synced
+    #[serde(skip)]
+    pub synced: Vec<Asset>,
+
+    /// The assets that failed to sync, together with the reason
+    #[serde(skip)]
+    pub failures: Vec<Failure>,
+}
+
+/// An asset that was skipped because an asset with the same checksum already is in the target
+#[derive(Serialize, Debug)]
+pub struct SkippedAsset {
+    /// File name of the asset in the source album
+    pub file: String,
+
+    /// File name of the asset with the same checksum in the target album
+    pub existing_file: String,
+}
+
+impl PairSummary {
+    /// Record an asset that failed to sync
+    pub fn fail(&mut self, failure: Failure) {
+        self.failed += 1;
+        self.failures.push(failure);
+    }
+
+    /// The counts of the pair on a single line, as printed by --quiet
+    pub fn to_line(&self) -> String {
+        let mut line = format!(
+            "{} ({}) -> {} ({}): ",
+            self.from, self.from_album, self.to, self.to_album
+        );
+        match &self.would_sync {
+            Some(assets) => line.push_str(&format!(
+                "{} would be synced ({}), {} skipped",
+                assets.len(),
+                HumanBytes(self.would_sync_bytes.unwrap_or_default()),
+                self.skipped
+            )),
+            None => line.push_str(&format!(
+                "{} synced, {} skipped, {} failed, {} downloaded",
+                self.uploaded,
+                self.skipped,
+                self.failed,
+                HumanBytes(self.bytes)
+            )),
+        }
+        if self.deferred > 0 {
+            line.push_str(&format!(", {} deferred", self.deferred));
+        }
+        match &self.would_remove {
+            Some(assets) if !assets.is_empty() => {
+                line.push_str(&format!(", {} would be removed", assets.len()));
+            }
+            _ if self.removed > 0 => line.push_str(&format!(", {} removed", self.removed)),
+            _ => {}
+        }
+        line
+    }
+}
+
+/// Result of a whole run, as printed by `--output json`
+#[derive(Serialize, Debug)]
+pub struct RunSummary<'a> {
+    pub dry_run: bool,
+
+    pub pairs: &'a [PairSummary],
+
+    /// Number of bytes downloaded over all pairs
+    pub bytes: u64,
+}
+
+impl<'a> RunSummary<'a> {
+    pub fn new(dry_run: bool, pairs: &'a [PairSummary]) -> Self {
+        Self {
+            dry_run,
+            pairs,
+            bytes: pairs.iter().map(|pair| pair.bytes).sum(),
+        }
+    }
+
+    /// A line for every pair and one with the totals of the run, as printed by --quiet
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for pair in self.pairs {
+            text.push_str(&pair.to_line());
+            text.push('\n');
+        }
+        let sum = |count: fn(&PairSummary) -> usize| self.pairs.iter().map(count).sum::<usize>();
+        let total = if self.dry_run {
+            format!(
+                "{} would be synced",
+                sum(|pair| pair.would_sync.as_ref().map_or(0, Vec::len))
+            )
+        } else {
+            format!(
+                "{} synced, {} failed, {} downloaded",
+                sum(|pair| pair.uploaded),
+                sum(|pair| pair.failed),
+                HumanBytes(self.bytes)
+            )
+        };
+        text.push_str(&format!("Total: {total}\n"));
+        text
+    }
+}
@@ -0,0 +1,146 @@
+use crate::{ratelimit, retry};
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use indicatif::HumanBytes;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::{Method, RequestBuilder, Response, StatusCode, Url};
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tracing::{debug, warn};
+
+/// Version of the tus protocol (https://tus.io/protocols/resumable-upload) that is spoken
+const VERSION: &str = "1.0.0";
+
+/// Header that carries the protocol version in every request and response
+const TUS_RESUMABLE: &str = "Tus-Resumable";
+
+/// Number of bytes sent with one request. An interrupted upload only has to repeat the part of
+/// the chunk the server didn't store.
+const CHUNK_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Whether the response to an OPTIONS request to the endpoint announces support for this
+/// protocol version and for creating uploads
+pub fn is_supported(res: &Response) -> bool {
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+    };
+    let listed = |name, value| header(name).split(',').any(|v| v.trim() == value);
+    res.status().is_success()
+        && listed("Tus-Version", VERSION)
+        && listed("Tus-Extension", "creation")
+}
+
+/// Upload the file at `path` to the tus endpoint. `request` builds an authenticated request for
+/// a URL. When a chunk fails, the upload is resumed from the offset the server has stored, up to
+/// `attempts` times in a row. Returns the response to the request that completed the upload,
+/// None if the upload completed but that response was lost.
+pub async fn upload(
+    request: impl Fn(Method, &str) -> RequestBuilder,
+    endpoint: &str,
+    path: &Path,
+    metadata: &[(&str, String)],
+    attempts: u32,
+) -> Result<Option<Response>> {
+    let size = tokio::fs::metadata(path).await?.len();
+    let metadata: Vec<_> = metadata
+        .iter()
+        .map(|(key, value)| format!("{key} {}", BASE64.encode(value)))
+        .collect();
+    let metadata = metadata.join(",");
+    let res = retry::with_retry(attempts, "Creating the resumable upload", || async {
+        let req = request(Method::POST, endpoint)
+            .header(TUS_RESUMABLE, VERSION)
+            .header("Upload-Length", size)
+            .header("Upload-Metadata", &metadata);
+        Ok(ratelimit::send(req).await?)
+    })
+    .await?;
+    if res.status() != StatusCode::CREATED {
+        bail!(
+            "Creating the resumable upload failed with status {}: {}",
+            res.status(),
+            res.text().await?
+        );
+    }
+    let location = res
+        .headers()
+        .get(LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .context("The server did not report the location of the resumable upload")?;
+    let location = Url::parse(endpoint)?.join(location)?.to_string();
+    debug!(%location, size, "Resumable upload created");
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut offset = 0;
+    let mut failures = 0;
+    loop {
+        let len = CHUNK_SIZE.min(size - offset);
+        let mut chunk = vec![0; len as usize];
+        file.seek(SeekFrom::Start(offset)).await?;
+        file.read_exact(&mut chunk).await?;
+        let req = request(Method::PATCH, &location)
+            .header(TUS_RESUMABLE, VERSION)
+            .header("Upload-Offset", offset)
+            .header(CONTENT_TYPE, "application/offset+octet-stream")
+            .body(chunk);
+        let reason = match ratelimit::send(req).await {
+            Ok(res) if res.status().is_success() => {
+                offset = upload_offset(&res)?;
+                if offset >= size {
+                    return Ok(Some(res));
+                }
+                failures = 0;
+                continue;
+            }
+            Ok(res) if !retry::is_retryable_status(res.status()) => {
+                bail!(
+                    "Resumable upload failed with status {}: {}",
+                    res.status(),
+                    res.text().await?
+                );
+            }
+            Ok(res) => res.status().to_string(),
+            Err(e) => e.to_string(),
+        };
+
+        failures += 1;
+        if failures >= attempts {
+            bail!(
+                "Resumable upload failed at {} of {} ({reason})",
+                HumanBytes(offset),
+                HumanBytes(size)
+            );
+        }
+        let delay = retry::backoff(failures);
+        warn!(
+            "Resumable upload interrupted at {} of {} ({reason}), resuming in {:.1}s",
+            HumanBytes(offset),
+            HumanBytes(size),
+            delay.as_secs_f32()
+        );
+        tokio::time::sleep(delay).await;
+        let req = request(Method::HEAD, &location).header(TUS_RESUMABLE, VERSION);
+        let res = ratelimit::send(req).await?;
+        if !res.status().is_success() {
+            bail!("Resuming the upload failed with status {}", res.status());
+        }
+        offset = upload_offset(&res)?;
+        if offset >= size {
+            return Ok(None);
+        }
+    }
+}
+
+/// The number of bytes the server has stored, as reported in a response
+fn upload_offset(res: &Response) -> Result<u64> {
+    res.headers()
+        .get("Upload-Offset")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .context("The server did not report the offset of the resumable upload")
+}
@@ -0,0 +1,768 @@
+//! Runs the binary against mock immich servers
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sha1::{Digest, Sha1};
+use std::process::Output;
+use tokio::process::Command;
+use wiremock::matchers::{body_string_contains, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn content(id: usize) -> String {
+    format!("content of asset-{id}")
+}
+
+fn asset_json(id: usize) -> serde_json::Value {
+    serde_json::json!({
+        "id": format!("asset-{id}"),
+        "checksum": BASE64.encode(Sha1::digest(content(id))),
+        "originalFileName": format!("IMG_{id}.jpg"),
+        "deviceAssetId": format!("device-asset-{id}"),
+        "deviceId": "device",
+        "fileCreatedAt": "2024-01-01T00:00:00.000Z",
+        "fileModifiedAt": "2024-01-01T00:00:00.000Z",
+        "type": "IMAGE",
+    })
+}
+
+/// Start a server with a shared album that contains the given assets
+async fn immich(album: &str, assets: &[usize]) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/shared-links/me"))
+        .and(query_param("key", album))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "album": {"id": album, "albumName": album},
+            "key": album,
+        })))
+        .mount(&server)
+        .await;
+    let assets: Vec<_> = assets.iter().copied().map(asset_json).collect();
+    Mock::given(method("GET"))
+        .and(path(format!("/api/albums/{album}")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "assets": assets,
+        })))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// Serve the original file of the asset
+async fn serve_download(server: &MockServer, id: usize) {
+    Mock::given(method("GET"))
+        .and(path(format!("/api/assets/asset-{id}/original")))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(content(id), "image/jpeg"))
+        .mount(server)
+        .await;
+}
+
+/// Accept uploads and adding assets to the target album
+async fn accept_uploads(server: &MockServer) {
+    Mock::given(method("POST"))
+        .and(path("/api/assets/bulk-upload-check"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "results": [],
+        })))
+        .mount(server)
+        .await;
+    for id in 1..=2 {
+        Mock::given(method("POST"))
+            .and(path("/api/assets"))
+            .and(body_string_contains(content(id)))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "id": format!("uploaded-{id}"),
+            })))
+            .mount(server)
+            .await;
+    }
+    Mock::given(method("PUT"))
+        .and(path("/api/albums/target/assets"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+        .mount(server)
+        .await;
+}
+
+/// Sync the source album to the target album
+async fn sync(source: &MockServer, target: &MockServer, args: &[&str]) -> Output {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    let raw = format!(
+        "[Source]\nshared_link = \"{}/share/source\"\nsync_with = []\n\n\
+         [Target]\nshared_link = \"{}/share/target\"\nsync_with = [\"Source\"]\n",
+        source.uri(),
+        target.uri()
+    );
+    std::fs::write(&config, raw).unwrap();
+    Command::new(env!("CARGO_BIN_EXE_iss"))
+        .arg("--config")
+        .arg(&config)
+        .args(["--retries", "1"])
+        .args(args)
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .await
+        .unwrap()
+}
+
+/// Bodies of the requests the server received with the given method and path
+async fn received(server: &MockServer, method: &str, path: &str) -> Vec<String> {
+    let requests = server.received_requests().await.unwrap();
+    requests
+        .into_iter()
+        .filter(|r| r.method.as_str() == method && r.url.path() == path)
+        .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+        .collect()
+}
+
+#[tokio::test]
+async fn syncs_missing_assets() {
+    let source = immich("source", &[1, 2]).await;
+    serve_download(&source, 1).await;
+    serve_download(&source, 2).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &[]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    let uploads = received(&target, "POST", "/api/assets").await;
+    assert_eq!(uploads.len(), 2);
+    assert!(uploads.iter().any(|body| body.contains(&content(1))));
+    assert!(uploads.iter().any(|body| body.contains(&content(2))));
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["uploaded-1","uploaded-2"]}"#]);
+}
+
+#[tokio::test]
+async fn continues_after_failed_download() {
+    let source = immich("source", &[1, 2]).await;
+    serve_download(&source, 1).await;
+    Mock::given(method("GET"))
+        .and(path("/api/assets/asset-2/original"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&source)
+        .await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &["--continue-on-error"]).await;
+
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Download failed for IMG_2.jpg"), "{stderr}");
+    let uploads = received(&target, "POST", "/api/assets").await;
+    assert_eq!(uploads.len(), 1);
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["uploaded-1"]}"#]);
+}
+
+#[tokio::test]
+async fn continues_after_failed_upload() {
+    let source = immich("source", &[1, 2]).await;
+    serve_download(&source, 1).await;
+    serve_download(&source, 2).await;
+    let target = immich("target", &[]).await;
+    Mock::given(method("POST"))
+        .and(path("/api/assets"))
+        .and(body_string_contains(content(2)))
+        .respond_with(ResponseTemplate::new(400).set_body_string("broken file"))
+        .with_priority(1)
+        .mount(&target)
+        .await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &["--continue-on-error"]).await;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("broken file"), "{stderr}");
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["uploaded-1"]}"#]);
+}
+
+#[tokio::test]
+async fn aborts_on_failed_download() {
+    let source = immich("source", &[1]).await;
+    Mock::given(method("GET"))
+        .and(path("/api/assets/asset-1/original"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&source)
+        .await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &[]).await;
+
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    assert!(received(&target, "POST", "/api/assets").await.is_empty());
+    assert!(
+        received(&target, "PUT", "/api/albums/target/assets")
+            .await
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn invalid_arguments_exit_with_usage_error() {
+    let source = immich("source", &[1]).await;
+    let target = immich("target", &[]).await;
+
+    let output = sync(&source, &target, &["--concurrency", "0"]).await;
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+
+    let output = sync(&source, &target, &["--no-such-flag"]).await;
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+
+    // Assets uploaded with a share link can't be kept out of its album
+    let output = sync(&source, &target, &["--no-add-to-album"]).await;
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("requires an API key for peer Target"),
+        "{stderr}"
+    );
+    assert!(
+        received(&target, "GET", "/api/albums/target")
+            .await
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn syncs_video_of_live_photo() {
+    let source = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/shared-links/me"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "album": {"id": "source", "albumName": "source"},
+            "key": "source",
+        })))
+        .mount(&source)
+        .await;
+    let mut still = asset_json(1);
+    still["livePhotoVideoId"] = "asset-2".into();
+    Mock::given(method("GET"))
+        .and(path("/api/albums/source"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "assets": [still],
+        })))
+        .mount(&source)
+        .await;
+    let mut video = asset_json(2);
+    video["originalFileName"] = "IMG_1.mov".into();
+    Mock::given(method("GET"))
+        .and(path("/api/assets/asset-2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(video))
+        .mount(&source)
+        .await;
+    serve_download(&source, 1).await;
+    serve_download(&source, 2).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &[]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    let uploads = received(&target, "POST", "/api/assets").await;
+    assert_eq!(uploads.len(), 2);
+    let still = uploads
+        .iter()
+        .find(|body| body.contains(&content(1)))
+        .unwrap();
+    assert!(still.contains("livePhotoVideoId"), "{still}");
+    assert!(still.contains("uploaded-2"), "{still}");
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["uploaded-1"]}"#]);
+}
+
+#[tokio::test]
+async fn syncs_from_link_to_link_without_config() {
+    let source = immich("source", &[1]).await;
+    serve_download(&source, 1).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = Command::new(env!("CARGO_BIN_EXE_iss"))
+        .args(["--from", &format!("{}/share/source", source.uri())])
+        .args(["--to", &format!("{}/share/target", target.uri())])
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .await
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["uploaded-1"]}"#]);
+}
+
+#[tokio::test]
+async fn dry_run_diff_lists_both_directions() {
+    let source = immich("source", &[1, 2]).await;
+    let target = immich("target", &[2, 3]).await;
+
+    let output = sync(&source, &target, &["--dry-run-diff"]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("+ IMG_1.jpg\n- IMG_3.jpg\n"), "{stdout}");
+    assert!(
+        stdout.contains("1 only in Source (-> Target), 1 only in Target (-> Source), 1 in both"),
+        "{stdout}"
+    );
+    assert!(received(&target, "POST", "/api/assets").await.is_empty());
+}
+
+#[tokio::test]
+async fn links_assets_that_exist_on_target_without_downloading() {
+    let source = immich("source", &[1, 2]).await;
+    serve_download(&source, 2).await;
+    let target = immich("target", &[]).await;
+    Mock::given(method("POST"))
+        .and(path("/api/assets/bulk-upload-check"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "results": [{"id": "asset-1", "assetId": "library-1"}],
+        })))
+        .with_priority(1)
+        .mount(&target)
+        .await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &[]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(
+        received(&source, "GET", "/api/assets/asset-1/original")
+            .await
+            .is_empty()
+    );
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["library-1","uploaded-2"]}"#]);
+}
+
+#[tokio::test]
+async fn verify_upload_detects_altered_uploads() {
+    let source = immich("source", &[1, 2]).await;
+    serve_download(&source, 1).await;
+    serve_download(&source, 2).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+    Mock::given(method("GET"))
+        .and(path("/api/assets/uploaded-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(asset_json(1)))
+        .mount(&target)
+        .await;
+    let mut truncated = asset_json(2);
+    truncated["checksum"] = "checksum-of-truncated-file".into();
+    Mock::given(method("GET"))
+        .and(path("/api/assets/uploaded-2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(truncated))
+        .mount(&target)
+        .await;
+
+    let output = sync(
+        &source,
+        &target,
+        &["--verify-upload", "--continue-on-error"],
+    )
+    .await;
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Checksum mismatch after upload of IMG_2.jpg"),
+        "{stderr}"
+    );
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["uploaded-1"]}"#]);
+}
+
+#[tokio::test]
+async fn syncs_from_asset_share() {
+    let source = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/shared-links/me"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "id": "link",
+            "key": "source",
+            "album": null,
+            "assets": [asset_json(1)],
+        })))
+        .mount(&source)
+        .await;
+    serve_download(&source, 1).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &[]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["uploaded-1"]}"#]);
+}
+
+#[tokio::test]
+async fn skips_listing_of_unchanged_pairs() {
+    let mut servers = Vec::new();
+    for album in ["source", "target"] {
+        let server = MockServer::start().await;
+        let metadata = serde_json::json!({
+            "id": album,
+            "albumName": album,
+            "assetCount": 1,
+            "updatedAt": "2024-01-01T00:00:00.000Z",
+        });
+        Mock::given(method("GET"))
+            .and(path("/api/shared-links/me"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "album": metadata,
+                "key": album,
+            })))
+            .mount(&server)
+            .await;
+        let mut listing = metadata.clone();
+        listing["assets"] = serde_json::json!([asset_json(1)]);
+        Mock::given(method("GET"))
+            .and(path(format!("/api/albums/{album}")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(listing))
+            .mount(&server)
+            .await;
+        servers.push(server);
+    }
+    let (source, target) = (&servers[0], &servers[1]);
+    let dir = tempfile::tempdir().unwrap();
+    let cache = dir.path().join("cache.json");
+    let args = ["--cache", cache.to_str().unwrap()];
+
+    let output = sync(source, target, &args).await;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(received(source, "GET", "/api/albums/source").await.len(), 1);
+
+    let output = sync(source, target, &args).await;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(received(source, "GET", "/api/albums/source").await.len(), 1);
+    assert_eq!(received(target, "GET", "/api/albums/target").await.len(), 1);
+
+    let output = sync(source, target, &[args[0], args[1], "--no-quick-check"]).await;
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(received(source, "GET", "/api/albums/source").await.len(), 2);
+}
+
+#[tokio::test]
+async fn force_uploads_assets_that_are_already_in_target() {
+    let source = immich("source", &[1, 2]).await;
+    serve_download(&source, 1).await;
+    serve_download(&source, 2).await;
+    let target = immich("target", &[1]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &["--force"]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(received(&target, "POST", "/api/assets").await.len(), 2);
+    assert!(
+        received(&target, "POST", "/api/assets/bulk-upload-check")
+            .await
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn large_syncs_need_confirmation_without_terminal() {
+    let source = immich("source", &[1, 2]).await;
+    serve_download(&source, 1).await;
+    serve_download(&source, 2).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &["--confirm-above", "1"]).await;
+
+    assert!(!output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--yes"));
+    assert!(received(&target, "POST", "/api/assets").await.is_empty());
+
+    let output = sync(&source, &target, &["--confirm-above", "1", "--yes"]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(received(&target, "POST", "/api/assets").await.len(), 2);
+}
+
+#[tokio::test]
+async fn falls_back_to_last_fetched_config() {
+    let source = immich("source", &[1]).await;
+    let target = immich("target", &[]).await;
+    let configs = MockServer::start().await;
+    let raw = format!(
+        "[Source]\nshared_link = \"{}/share/source\"\nsync_with = []\n\n\
+         [Target]\nshared_link = \"{}/share/target\"\nsync_with = [\"Source\"]\n",
+        source.uri(),
+        target.uri()
+    );
+    Mock::given(method("GET"))
+        .and(path("/iss.toml"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(raw))
+        .up_to_n_times(1)
+        .mount(&configs)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/iss.toml"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&configs)
+        .await;
+    let url = format!("{}/iss.toml", configs.uri());
+    let cache = tempfile::tempdir().unwrap();
+    let run = |cache: &std::path::Path| {
+        Command::new(env!("CARGO_BIN_EXE_iss"))
+            .args(["--config", &url, "--dry-run"])
+            .env("XDG_CACHE_HOME", cache)
+            .env("RUST_BACKTRACE", "0")
+            .output()
+    };
+
+    let output = run(cache.path()).await.unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let output = run(cache.path()).await.unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let fallback = stderr.lines().find(|l| l.contains("using the copy from"));
+    assert!(
+        fallback.is_some_and(|l| l.contains("ERROR") && l.contains("503")),
+        "{stderr}"
+    );
+
+    let empty = tempfile::tempdir().unwrap();
+    let output = run(empty.path()).await.unwrap();
+    assert!(!output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("503"), "{stderr}");
+}
+
+#[tokio::test]
+async fn pipes_downloads_into_uploads() {
+    let source = immich("source", &[1, 2]).await;
+    serve_download(&source, 1).await;
+    // Not the content of asset 2, so its checksum doesn't match
+    Mock::given(method("GET"))
+        .and(path("/api/assets/asset-2/original"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(content(3), "image/jpeg"))
+        .mount(&source)
+        .await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &["--pipe", "--continue-on-error"]).await;
+
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Checksum mismatch for IMG_2.jpg"),
+        "{stderr}"
+    );
+    let uploads = received(&target, "POST", "/api/assets").await;
+    assert_eq!(uploads.len(), 1);
+    assert!(uploads[0].contains(&content(1)));
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["uploaded-1"]}"#]);
+}
+
+#[tokio::test]
+async fn skips_empty_source_albums() {
+    let source = immich("source", &[]).await;
+    let target = immich("target", &[1]).await;
+
+    let output = sync(&source, &target, &[]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Source album 'source' of Source is empty, skipping"),
+        "{stderr}"
+    );
+    assert!(!stderr.contains("Adding assets from"), "{stderr}");
+    assert!(
+        received(&target, "GET", "/api/albums/target")
+            .await
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn applies_reviewed_plan() {
+    let source = immich("source", &[1, 2, 3]).await;
+    serve_download(&source, 1).await;
+    serve_download(&source, 2).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+    let dir = tempfile::tempdir().unwrap();
+    let plan_path = dir.path().join("plan.json");
+    let plan_arg = plan_path.to_str().unwrap();
+
+    let output = sync(&source, &target, &["--dry-run", "--plan-out", plan_arg]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    assert!(received(&target, "POST", "/api/assets").await.is_empty());
+    let mut plan: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&plan_path).unwrap()).unwrap();
+    let assets = plan["pairs"][0]["assets"].as_array_mut().unwrap();
+    assert_eq!(assets.len(), 3);
+    // The review drops asset 3, asset 2 changed since the plan was made
+    assets.truncate(2);
+    assets[1]["checksum"] = "outdated".into();
+    std::fs::write(&plan_path, plan.to_string()).unwrap();
+
+    let output = sync(&source, &target, &["--apply-plan", plan_arg]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1 planned assets are not in Source (source) anymore or changed"),
+        "{stderr}"
+    );
+    let uploads = received(&target, "POST", "/api/assets").await;
+    assert_eq!(uploads.len(), 1);
+    assert!(uploads[0].contains(&content(1)));
+    let added = received(&target, "PUT", "/api/albums/target/assets").await;
+    assert_eq!(added, [r#"{"ids":["uploaded-1"]}"#]);
+}
+
+#[tokio::test]
+async fn quiet_prints_only_the_summary() {
+    let source = immich("source", &[1, 2]).await;
+    serve_download(&source, 1).await;
+    serve_download(&source, 2).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &["--quiet"]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.is_empty(), "{stderr}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout,
+        "Source (source) -> Target (target): 2 synced, 0 skipped, 0 failed, 36 B downloaded\n\
+         Total: 2 synced, 0 failed, 36 B downloaded\n"
+    );
+}
+
+#[tokio::test]
+async fn quiet_still_prints_stats() {
+    let source = immich("source", &[1]).await;
+    serve_download(&source, 1).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+
+    let output = sync(&source, &target, &["--quiet", "--stats"]).await;
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Requests: "), "{stdout}");
+    assert!(stdout.contains("Downloaded: 18 B\n"), "{stdout}");
+}
+
+#[tokio::test]
+async fn reuses_downloads_of_aborted_pairs() {
+    let source = immich("source", &[1]).await;
+    serve_download(&source, 1).await;
+    // The share link of the first target expires before the upload
+    let expired = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/api/shared-links/me"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "album": {"id": "expired", "albumName": "expired"},
+            "key": "expired",
+        })))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&expired)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/shared-links/me"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&expired)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/albums/expired"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "assets": [],
+        })))
+        .mount(&expired)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/assets"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&expired)
+        .await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("config.toml");
+    let raw = format!(
+        "[Source]\nshared_link = \"{}/share/source\"\nsync_with = []\n\n\
+         [Expired]\nshared_link = \"{}/share/expired\"\nsync_with = [\"Source\"]\n\n\
+         [Target]\nshared_link = \"{}/share/target\"\nsync_with = [\"Source\"]\n",
+        source.uri(),
+        expired.uri(),
+        target.uri()
+    );
+    std::fs::write(&config, raw).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_iss"))
+        .arg("--config")
+        .arg(&config)
+        .args(["--retries", "1"])
+        .env("RUST_BACKTRACE", "0")
+        .output()
+        .await
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(2), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("rejected"), "{stderr}");
+    let downloads = received(&source, "GET", "/api/assets/asset-1/original").await;
+    assert_eq!(downloads.len(), 1);
+    let uploads = received(&target, "POST", "/api/assets").await;
+    assert_eq!(uploads.len(), 1);
+    assert!(uploads[0].contains(&content(1)));
+}
+
+#[tokio::test]
+async fn aborted_runs_write_failure_metrics() {
+    let source = immich("source", &[1]).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+    let dir = tempfile::tempdir().unwrap();
+    let metrics = dir.path().join("iss.prom");
+    // Left from an earlier successful run
+    std::fs::write(&metrics, "iss_last_run_success 1\n").unwrap();
+
+    let output = sync(
+        &source,
+        &target,
+        &["--metrics-file", metrics.to_str().unwrap()],
+    )
+    .await;
+
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    let written = std::fs::read_to_string(&metrics).unwrap();
+    assert!(written.contains("\niss_last_run_success 0\n"), "{written}");
+}
+
+#[tokio::test]
+async fn aborted_runs_are_reported() {
+    let source = immich("source", &[1]).await;
+    let target = immich("target", &[]).await;
+    accept_uploads(&target).await;
+    let dir = tempfile::tempdir().unwrap();
+    let report = dir.path().join("report.jsonl");
+
+    let output = sync(&source, &target, &["--report", report.to_str().unwrap()]).await;
+
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    let written = std::fs::read_to_string(&report).unwrap();
+    let run: serde_json::Value = serde_json::from_str(&written).unwrap();
+    let error = run["error"].as_str().unwrap();
+    assert!(error.contains("IMG_1.jpg"), "{error}");
+}